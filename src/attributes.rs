@@ -0,0 +1,337 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::error::XmlError;
+use crate::namespaces::split_qname;
+use crate::tokenizer::{is_whitespace, Position};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedAttribute {
+    pub name: String,
+    // The value between the quotes, references are not decoded yet
+    pub value: String,
+    pub name_position: Position,
+    pub value_position: Position,
+}
+
+// Reads one `name = "value"` or `name='value'` from the start of `input`.
+// Returns the attribute and how many bytes of `input` it used.
+pub fn read_attribute(input: &str, start: Position) -> Result<(ParsedAttribute, usize), XmlError> {
+    let position_at = |rest: &str| start.advanced_by(&input[..input.len() - rest.len()]);
+    let unexpected = |rest: &str, expected: &str| match rest.chars().next() {
+        Some(character) => XmlError::InvalidCharacter { character, position: position_at(rest) },
+        None => XmlError::UnexpectedEof { expected: expected.to_string(), position: position_at(rest) },
+    };
+
+    let name_end = input.find(|c: char| is_whitespace(c) || matches!(c, '=' | '/' | '>' | '<' | '"' | '\'')).unwrap_or(input.len());
+    if name_end == 0 {
+        return Err(unexpected(input, "an attribute name"));
+    }
+    let name = input[..name_end].to_string();
+
+    let rest = input[name_end..].trim_start_matches(is_whitespace);
+    let Some(rest) = rest.strip_prefix('=') else {
+        if rest.is_empty() {
+            return Err(unexpected(rest, "\"=\""));
+        }
+        return Err(XmlError::InvalidAttribute { message: format!("expected \"=\" after {name}"), position: position_at(rest) });
+    };
+
+    let rest = rest.trim_start_matches(is_whitespace);
+    let quote = match rest.chars().next() {
+        Some(quote @ ('"' | '\'')) => quote,
+        Some(_) => return Err(XmlError::InvalidAttribute { message: format!("value of {name} must be quoted"), position: position_at(rest) }),
+        None => return Err(unexpected(rest, "a quoted value")),
+    };
+    let value_position = position_at(&rest[1..]);
+    let Some(value_end) = rest[1..].find(quote) else {
+        return Err(XmlError::UnexpectedEof { expected: "a closing quote".to_string(), position: start.advanced_by(input) });
+    };
+    let value = rest[1..value_end + 1].to_string();
+    let consumed = input.len() - rest.len() + value_end + 2;
+
+    Ok((ParsedAttribute { name, value, name_position: start, value_position }, consumed))
+}
+
+// Reads a whitespace separated list of attributes, every name may appear only once
+pub fn parse_attributes(input: &str, start: Position) -> Result<Vec<ParsedAttribute>, XmlError> {
+    let mut result: Vec<ParsedAttribute> = Vec::new();
+    let mut names: HashSet<String> = HashSet::new();
+    let mut rest = input;
+    loop {
+        let trimmed = rest.trim_start_matches(is_whitespace);
+        if trimmed.is_empty() {
+            return Ok(result);
+        }
+        let position = start.advanced_by(&input[..input.len() - trimmed.len()]);
+        if !result.is_empty() && trimmed.len() == rest.len() {
+            return Err(XmlError::InvalidAttribute { message: "attributes must be separated by whitespace".to_string(), position });
+        }
+        let (attribute, consumed) = read_attribute(trimmed, position)?;
+        check_duplicate(&mut names, &attribute)?;
+        result.push(attribute);
+        rest = &trimmed[consumed..];
+    }
+}
+
+// `names` holds the names seen so far in the tag, the name of `attribute` is added to it
+pub fn check_duplicate(names: &mut HashSet<String>, attribute: &ParsedAttribute) -> Result<(), XmlError> {
+    if !names.insert(attribute.name.clone()) {
+        return Err(XmlError::InvalidAttribute {
+            message: format!("duplicate attribute {}", attribute.name),
+            position: attribute.name_position,
+        });
+    }
+    Ok(())
+}
+
+// Attribute-value normalization: literal tabs and line breaks become spaces.
+// A "\r\n" pair counts as one line break, whitespace written as a character reference is left alone.
+pub fn normalize_attribute_value(value: &str) -> String {
+    value.replace("\r\n", " ").replace(['\t', '\n', '\r'], " ")
+}
+
+// Whether xml:space="preserve" applies to an element with these attributes, `inherited` is the value of its parent.
+// Values other than "preserve" and "default" leave the inherited value in place.
+pub fn is_space_preserved(attributes: &Attributes, inherited: bool) -> bool {
+    match attributes.get("xml:space").map(String::as_str) {
+        Some("preserve") => true,
+        Some("default") => false,
+        _ => inherited,
+    }
+}
+
+// One attribute of an element, the raw value is only kept when asked for in the ParseOptions
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    name: String,
+    value: String,
+    raw_value: Option<String>,
+    namespace: Option<String>,
+}
+
+impl Attribute {
+    pub fn new(name: String, value: String, raw_value: Option<String>) -> Attribute {
+        Attribute { name, value, raw_value, namespace: None }
+    }
+
+    pub fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn get_value(&self) -> &String {
+        &self.value
+    }
+
+    pub fn get_raw_value(&self) -> Option<&String> {
+        self.raw_value.as_ref()
+    }
+
+    pub fn get_prefix(&self) -> Option<&str> {
+        split_qname(&self.name).0
+    }
+
+    pub fn get_local_name(&self) -> &str {
+        split_qname(&self.name).1
+    }
+
+    // Unprefixed attributes are in no namespace
+    pub fn get_namespace_uri(&self) -> Option<&String> {
+        self.namespace.as_ref()
+    }
+
+    pub fn set_namespace_uri(&mut self, namespace: Option<String>) {
+        self.namespace = namespace;
+    }
+}
+
+// The attributes of an element in document order, with an index for lookup by name
+#[derive(Clone, Default, PartialEq)]
+pub struct Attributes {
+    list: Vec<Attribute>,
+    index: HashMap<String, usize>,
+}
+
+impl Attributes {
+    pub fn new() -> Attributes {
+        Attributes::default()
+    }
+
+    // Adds the attribute at the end, an attribute with the same name is replaced in place
+    pub fn push(&mut self, attribute: Attribute) -> Option<Attribute> {
+        match self.index.get(&attribute.name) {
+            Some(&position) => Some(std::mem::replace(&mut self.list[position], attribute)),
+            None => {
+                self.index.insert(attribute.name.clone(), self.list.len());
+                self.list.push(attribute);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, name: String, value: String) -> Option<String> {
+        self.push(Attribute::new(name, value, None)).map(|old| old.value)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Attribute> {
+        let position = self.index.remove(name)?;
+        let attribute = self.list.remove(position);
+        for later in &self.list[position..] {
+            if let Some(entry) = self.index.get_mut(&later.name) {
+                *entry -= 1;
+            }
+        }
+        Some(attribute)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.get_attribute(name).map(|attribute| &attribute.value)
+    }
+
+    pub fn get_attribute(&self, name: &str) -> Option<&Attribute> {
+        self.index.get(name).map(|&position| &self.list[position])
+    }
+
+    // Lookup by expanded name, the prefix used in the document does not matter
+    pub fn get_ns(&self, namespace: Option<&str>, local_name: &str) -> Option<&Attribute> {
+        self.list.iter().find(|attribute| attribute.namespace.as_deref() == namespace && attribute.get_local_name() == local_name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.index.contains_key(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Attribute> {
+        self.list.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, Attribute> {
+        self.list.iter_mut()
+    }
+}
+
+impl<'a> IntoIterator for &'a Attributes {
+    type Item = &'a Attribute;
+    type IntoIter = std::slice::Iter<'a, Attribute>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.list.iter()
+    }
+}
+
+impl FromIterator<(String, String)> for Attributes {
+    fn from_iter<I: IntoIterator<Item = (String, String)>>(iter: I) -> Attributes {
+        let mut attributes = Attributes::new();
+        for (name, value) in iter {
+            attributes.insert(name, value);
+        }
+        attributes
+    }
+}
+
+// A map has no order of its own, the attributes end up in its iteration order
+impl From<HashMap<String, String>> for Attributes {
+    fn from(map: HashMap<String, String>) -> Attributes {
+        map.into_iter().collect()
+    }
+}
+
+// Printed like a map, in document order
+impl fmt::Debug for Attributes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.list.iter().map(|attribute| (&attribute.name, &attribute.value))).finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names_and_values(input: &str) -> Vec<(String, String)> {
+        parse_attributes(input, Position::default()).unwrap().into_iter().map(|a| (a.name, a.value)).collect()
+    }
+
+    #[test]
+    fn test_quote_styles_and_whitespace() {
+        let attributes = names_and_values(" a=\"b\" c = 'd'\n\te\t=\n\"it's\" f='say \"hi\"'");
+        assert_eq!(attributes, vec![
+            ("a".to_string(), "b".to_string()),
+            ("c".to_string(), "d".to_string()),
+            ("e".to_string(), "it's".to_string()),
+            ("f".to_string(), "say \"hi\"".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_short_and_empty_values() {
+        assert_eq!(names_and_values("a=\"b\""), vec![("a".to_string(), "b".to_string())]);
+        assert_eq!(names_and_values("a=''"), vec![("a".to_string(), String::new())]);
+        assert!(names_and_values("   ").is_empty());
+    }
+
+    #[test]
+    fn test_positions() {
+        let attributes = parse_attributes("x='1'\n  y=\"2\"", Position::default()).unwrap();
+        assert_eq!(attributes[1].name_position, Position { line: 2, column: 3, offset: 8 });
+        assert_eq!(attributes[1].value_position, Position { line: 2, column: 6, offset: 11 });
+    }
+
+    #[test]
+    fn test_malformed_attributes() {
+        let parse = |input: &str| parse_attributes(input, Position::default());
+        assert_eq!(parse("a=1").unwrap_err(), XmlError::InvalidAttribute {
+            message: "value of a must be quoted".to_string(),
+            position: Position { line: 1, column: 3, offset: 2 },
+        });
+        assert!(matches!(parse("a \"1\""), Err(XmlError::InvalidAttribute { .. })));
+        assert!(matches!(parse("a=\"1"), Err(XmlError::UnexpectedEof { .. })));
+        assert!(matches!(parse("a="), Err(XmlError::UnexpectedEof { .. })));
+        assert!(matches!(parse("a=\"1\"b=\"2\""), Err(XmlError::InvalidAttribute { .. })));
+        assert!(matches!(parse("=\"1\""), Err(XmlError::InvalidCharacter { character: '=', .. })));
+    }
+
+    #[test]
+    fn test_duplicate_attribute() {
+        let error = parse_attributes("a=\"1\" b=\"2\" a=\"3\"", Position::default()).unwrap_err();
+        assert_eq!(error, XmlError::InvalidAttribute {
+            message: "duplicate attribute a".to_string(),
+            position: Position { line: 1, column: 13, offset: 12 },
+        });
+    }
+
+    #[test]
+    fn test_normalization() {
+        assert_eq!(normalize_attribute_value("a\tb\r\nc\nd\re"), "a b c d e");
+        assert_eq!(normalize_attribute_value("a&#10;b"), "a&#10;b");
+    }
+
+    #[test]
+    fn test_attributes_keep_document_order() {
+        let mut attributes: Attributes = ["zeta", "alpha", "mid"].iter().map(|name| (name.to_string(), name.to_uppercase())).collect();
+        let names: Vec<&String> = attributes.iter().map(|attribute| attribute.get_name()).collect();
+        assert_eq!(names, vec!["zeta", "alpha", "mid"]);
+        assert_eq!(attributes.get("alpha").unwrap(), "ALPHA");
+
+        assert_eq!(attributes.insert("alpha".to_string(), "changed".to_string()), Some("ALPHA".to_string()));
+        assert_eq!(attributes.iter().nth(1).unwrap().get_value(), "changed");
+        assert_eq!(format!("{attributes:?}"), "{\"zeta\": \"ZETA\", \"alpha\": \"changed\", \"mid\": \"MID\"}");
+    }
+
+    #[test]
+    fn test_attributes_remove() {
+        let mut attributes: Attributes = [("a", "1"), ("b", "2"), ("c", "3")].iter().map(|(n, v)| (n.to_string(), v.to_string())).collect();
+        assert_eq!(attributes.remove("a").unwrap().get_value(), "1");
+        assert_eq!(attributes.remove("a"), None);
+        assert_eq!(attributes.len(), 2);
+        assert_eq!(attributes.get("c").unwrap(), "3");
+        assert!(!attributes.contains("a"));
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+use crate::error::XmlError;
+use crate::tokenizer::{is_name_char, is_whitespace, Position};
+
+// The <!DOCTYPE ...> declaration: root name, external identifiers and internal subset
+#[derive(Debug, Clone, PartialEq)]
+pub struct Doctype {
+    root_name: String,
+    public_id: Option<String>,
+    system_id: Option<String>,
+    internal_subset: Option<String>,
+    entities: HashMap<String, String>,
+}
+
+impl Doctype {
+    // `content` is everything between "<!DOCTYPE" and ">", `position` is where it starts
+    pub fn parse(content: &str, position: Position) -> Result<Doctype, XmlError> {
+        let mut scanner = Scanner { input: content, rest: content, start: position };
+
+        scanner.skip_whitespace();
+        let root_name = scanner.read_name("the root element name")?;
+        scanner.skip_whitespace();
+
+        let mut public_id = None;
+        let mut system_id = None;
+        if scanner.eat("PUBLIC") {
+            public_id = Some(scanner.read_quoted("a public identifier")?);
+            system_id = Some(scanner.read_quoted("a system identifier")?);
+        } else if scanner.eat("SYSTEM") {
+            system_id = Some(scanner.read_quoted("a system identifier")?);
+        }
+        scanner.skip_whitespace();
+
+        let mut internal_subset = None;
+        let mut entities = HashMap::new();
+        if scanner.eat("[") {
+            let subset_start = scanner.rest;
+            read_internal_subset(&mut scanner, &mut entities)?;
+            internal_subset = Some(subset_start[..subset_start.len() - scanner.rest.len()].to_string());
+            scanner.eat("]");
+            scanner.skip_whitespace();
+        }
+        if !scanner.rest.is_empty() {
+            return Err(scanner.syntax_error("Unexpected content in <!DOCTYPE"));
+        }
+
+        Ok(Doctype { root_name, public_id, system_id, internal_subset, entities })
+    }
+
+    pub fn get_root_name(&self) -> &String {
+        &self.root_name
+    }
+
+    pub fn get_public_id(&self) -> Option<&String> {
+        self.public_id.as_ref()
+    }
+
+    pub fn get_system_id(&self) -> Option<&String> {
+        self.system_id.as_ref()
+    }
+
+    // The text between "[" and "]" as written in the document
+    pub fn get_internal_subset(&self) -> Option<&String> {
+        self.internal_subset.as_ref()
+    }
+
+    // Internal general entities, the value is the replacement text as declared
+    pub fn get_entities(&self) -> &HashMap<String, String> {
+        &self.entities
+    }
+
+    pub fn get_entity(&self, name: &str) -> Option<&String> {
+        self.entities.get(name)
+    }
+}
+
+struct Scanner<'a> {
+    input: &'a str,
+    rest: &'a str,
+    start: Position,
+}
+
+impl<'a> Scanner<'a> {
+    fn position(&self) -> Position {
+        self.start.advanced_by(&self.input[..self.input.len() - self.rest.len()])
+    }
+
+    fn syntax_error(&self, message: &str) -> XmlError {
+        XmlError::InvalidSyntax { message: message.to_string(), position: self.position() }
+    }
+
+    fn unexpected(&self, expected: &str) -> XmlError {
+        match self.rest.chars().next() {
+            Some(character) => XmlError::InvalidCharacter { character, position: self.position() },
+            None => XmlError::UnexpectedEof { expected: expected.to_string(), position: self.position() },
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> bool {
+        let before = self.rest.len();
+        self.rest = self.rest.trim_start_matches(is_whitespace);
+        self.rest.len() != before
+    }
+
+    fn eat(&mut self, literal: &str) -> bool {
+        match self.rest.strip_prefix(literal) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn read_name(&mut self, expected: &str) -> Result<String, XmlError> {
+        let end = self.rest.find(|c: char| !is_name_char(c)).unwrap_or(self.rest.len());
+        if end == 0 {
+            return Err(self.unexpected(expected));
+        }
+        let name = self.rest[..end].to_string();
+        self.rest = &self.rest[end..];
+        Ok(name)
+    }
+
+    fn read_quoted(&mut self, expected: &str) -> Result<String, XmlError> {
+        self.skip_whitespace();
+        let quote = match self.rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => return Err(self.unexpected(expected)),
+        };
+        match self.rest[1..].find(quote) {
+            Some(end) => {
+                let value = self.rest[1..end + 1].to_string();
+                self.rest = &self.rest[end + 2..];
+                Ok(value)
+            }
+            None => {
+                self.rest = "";
+                Err(XmlError::UnexpectedEof { expected: "a closing quote".to_string(), position: self.position() })
+            }
+        }
+    }
+
+    // Skips past `terminator`, used for declarations this parser does not interpret
+    fn skip_past(&mut self, terminator: &str) -> Result<(), XmlError> {
+        match self.rest.find(terminator) {
+            Some(index) => {
+                self.rest = &self.rest[index + terminator.len()..];
+                Ok(())
+            }
+            None => {
+                self.rest = "";
+                Err(XmlError::UnexpectedEof { expected: format!("\"{terminator}\""), position: self.position() })
+            }
+        }
+    }
+
+    // Skips a markup declaration up to its ">", quoted values may contain ">"
+    fn skip_declaration(&mut self) -> Result<(), XmlError> {
+        let mut quote: Option<char> = None;
+        for (index, c) in self.rest.char_indices() {
+            match (quote, c) {
+                (Some(q), _) if c == q => quote = None,
+                (Some(_), _) => (),
+                (None, '"' | '\'') => quote = Some(c),
+                (None, '>') => {
+                    self.rest = &self.rest[index + 1..];
+                    return Ok(());
+                }
+                _ => (),
+            }
+        }
+        self.rest = "";
+        Err(XmlError::UnexpectedEof { expected: "\">\"".to_string(), position: self.position() })
+    }
+}
+
+// Reads declarations until the closing "]", only general entity declarations are kept
+fn read_internal_subset(scanner: &mut Scanner, entities: &mut HashMap<String, String>) -> Result<(), XmlError> {
+    loop {
+        scanner.skip_whitespace();
+        if scanner.rest.starts_with(']') {
+            return Ok(());
+        }
+        if scanner.rest.is_empty() {
+            return Err(scanner.unexpected("\"]\" to close the internal subset"));
+        }
+        if scanner.eat("<!--") {
+            scanner.skip_past("-->")?;
+        } else if scanner.eat("<?") {
+            scanner.skip_past("?>")?;
+        } else if scanner.eat("<!ENTITY") {
+            read_entity_declaration(scanner, entities)?;
+        } else if scanner.rest.starts_with("<!") {
+            scanner.skip_declaration()?;
+        } else if scanner.eat("%") {
+            // Parameter entity references are not expanded
+            scanner.read_name("a parameter entity name")?;
+            if !scanner.eat(";") {
+                return Err(scanner.unexpected("\";\""));
+            }
+        } else {
+            return Err(scanner.syntax_error("Expected a markup declaration in the internal subset"));
+        }
+    }
+}
+
+// <!ENTITY name "value">, parameter and external entities are read but not registered
+fn read_entity_declaration(scanner: &mut Scanner, entities: &mut HashMap<String, String>) -> Result<(), XmlError> {
+    if !scanner.skip_whitespace() {
+        return Err(scanner.unexpected("whitespace after <!ENTITY"));
+    }
+    let parameter = scanner.eat("%");
+    scanner.skip_whitespace();
+    let name = scanner.read_name("an entity name")?;
+    scanner.skip_whitespace();
+
+    if scanner.rest.starts_with(['"', '\'']) {
+        let value = scanner.read_quoted("an entity value")?;
+        // The first declaration of an entity is binding
+        if !parameter && !entities.contains_key(&name) {
+            entities.insert(name, value);
+        }
+    } else if scanner.eat("SYSTEM") || scanner.eat("PUBLIC") {
+        scanner.skip_declaration()?;
+        return Ok(());
+    } else {
+        return Err(scanner.unexpected("an entity value"));
+    }
+
+    scanner.skip_whitespace();
+    if !scanner.eat(">") {
+        return Err(scanner.unexpected("\">\" to close <!ENTITY"));
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Result<Doctype, XmlError> {
+        Doctype::parse(content, Position::default())
+    }
+
+    #[test]
+    fn test_root_name_only() {
+        let doctype = parse(" html").unwrap();
+        assert_eq!(doctype.get_root_name(), "html");
+        assert_eq!(doctype.get_public_id(), None);
+        assert_eq!(doctype.get_system_id(), None);
+        assert_eq!(doctype.get_internal_subset(), None);
+    }
+
+    #[test]
+    fn test_external_identifiers() {
+        let doctype = parse(" html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\"\n  'http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd'").unwrap();
+        assert_eq!(doctype.get_public_id().unwrap(), "-//W3C//DTD XHTML 1.0 Strict//EN");
+        assert_eq!(doctype.get_system_id().unwrap(), "http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd");
+
+        let doctype = parse(" note SYSTEM \"note.dtd\"").unwrap();
+        assert_eq!(doctype.get_public_id(), None);
+        assert_eq!(doctype.get_system_id().unwrap(), "note.dtd");
+    }
+
+    #[test]
+    fn test_internal_subset_entities() {
+        let subset = "
+    <!ELEMENT note (#PCDATA)>
+    <!ATTLIST note lang CDATA \"en>\">
+    <!-- The company <name> -->
+    <!ENTITY company \"Acme &amp; Sons\">
+    <!ENTITY company \"Ignored\">
+    <!ENTITY % shared \"shared\">
+    %shared;
+    <!ENTITY logo SYSTEM \"logo.png\" NDATA png>
+    <!ENTITY motto 'Built to \"last\"'>
+";
+        let doctype = parse(&format!(" note [{subset}]")).unwrap();
+        assert_eq!(doctype.get_internal_subset().unwrap(), subset);
+        assert_eq!(doctype.get_entities().len(), 2);
+        assert_eq!(doctype.get_entity("company").unwrap(), "Acme &amp; Sons");
+        assert_eq!(doctype.get_entity("motto").unwrap(), "Built to \"last\"");
+        assert_eq!(doctype.get_entity("shared"), None);
+        assert_eq!(doctype.get_entity("logo"), None);
+    }
+
+    #[test]
+    fn test_invalid_doctypes() {
+        assert!(matches!(parse(""), Err(XmlError::UnexpectedEof { .. })));
+        assert!(parse(" note SYSTEM").is_err());
+        assert!(parse(" note [<!ENTITY a \"b\">").is_err());
+        assert!(parse(" note [<!ENTITY a b>]").is_err());
+        assert!(parse(" note [ junk ]").is_err());
+
+        let error = parse(" note\n  [<!ENTITY a 'b'>] extra").unwrap_err();
+        assert_eq!(error.get_position().unwrap(), Position { line: 2, column: 21, offset: 26 });
+    }
+}
@@ -0,0 +1,243 @@
+use std::fmt;
+use crate::error::XmlError;
+use crate::attributes::{parse_attributes, ParsedAttribute};
+use crate::tokenizer::Position;
+use crate::doctype::Doctype;
+use crate::tree_struct::{Node, NodeId, NodeIdAllocator, NodeKind};
+
+// The <?xml version="..." encoding="..." standalone="..."?> line at the top of a document
+#[derive(Debug, Clone, PartialEq)]
+pub struct XmlDeclaration {
+    version: String,
+    encoding: Option<String>,
+    standalone: Option<bool>,
+}
+
+impl XmlDeclaration {
+    pub fn new(version: String, encoding: Option<String>, standalone: Option<bool>) -> XmlDeclaration {
+        XmlDeclaration { version, encoding, standalone }
+    }
+
+    // `data` is everything between "<?xml" and "?>", `position` is where it starts
+    pub fn parse(data: &str, position: Position) -> Result<XmlDeclaration, XmlError> {
+        let pseudo_attributes = parse_attributes(data, position)?;
+        if pseudo_attributes.first().map(|attribute| attribute.name.as_str()) != Some("version") {
+            return Err(XmlError::InvalidSyntax {
+                message: "The XML declaration must start with a version".to_string(),
+                position,
+            });
+        }
+        let mut declaration = XmlDeclaration::new(String::new(), None, None);
+
+        // The pseudo-attributes must appear in this order and only version is required
+        let mut expected = ["version", "encoding", "standalone"].iter().peekable();
+        for ParsedAttribute { name, value, value_position, .. } in &pseudo_attributes {
+            while expected.peek().is_some_and(|&&expected_name| expected_name != name) {
+                expected.next();
+            }
+            if expected.next().is_none() {
+                return Err(XmlError::InvalidSyntax {
+                    message: format!("Unexpected {name} in the XML declaration"),
+                    position: *value_position,
+                });
+            }
+            match name.as_str() {
+                "version" => {
+                    if value != "1.0" && value != "1.1" {
+                        return Err(XmlError::InvalidSyntax {
+                            message: format!("Unsupported XML version {value}, expected 1.0 or 1.1"),
+                            position: *value_position,
+                        });
+                    }
+                    declaration.version = value.clone();
+                }
+                "encoding" => {
+                    if !is_encoding_name(value) {
+                        return Err(XmlError::InvalidSyntax {
+                            message: format!("{value} is not a valid encoding name"),
+                            position: *value_position,
+                        });
+                    }
+                    declaration.encoding = Some(value.clone());
+                }
+                _ => {
+                    declaration.standalone = match value.as_str() {
+                        "yes" => Some(true),
+                        "no" => Some(false),
+                        _ => return Err(XmlError::InvalidSyntax {
+                            message: format!("standalone must be yes or no, found {value}"),
+                            position: *value_position,
+                        }),
+                    };
+                }
+            }
+        }
+        Ok(declaration)
+    }
+
+    pub fn get_version(&self) -> &String {
+        &self.version
+    }
+
+    pub fn get_encoding(&self) -> Option<&String> {
+        self.encoding.as_ref()
+    }
+
+    pub fn get_standalone(&self) -> Option<bool> {
+        self.standalone
+    }
+}
+
+impl fmt::Display for XmlDeclaration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<?xml version=\"{}\"", self.version)?;
+        if let Some(encoding) = &self.encoding {
+            write!(f, " encoding=\"{encoding}\"")?;
+        }
+        if let Some(standalone) = self.standalone {
+            write!(f, " standalone=\"{}\"", if standalone { "yes" } else { "no" })?;
+        }
+        write!(f, "?>")
+    }
+}
+
+// A parsed XML file, the nodes plus everything that is not part of the element tree.
+// The document owns all nodes in an arena, NodeId handles give access to them in constant time.
+// The slot of a removed node stays empty, so ids are never reused.
+#[derive(Debug)]
+pub struct Document {
+    declaration: Option<XmlDeclaration>,
+    doctype: Option<Doctype>,
+    pub(crate) nodes: Vec<Option<Node>>,
+    // The nodes without a parent that are part of the document, in document order
+    pub(crate) top_level: Vec<NodeId>,
+    pub(crate) ids: NodeIdAllocator,
+}
+
+impl Document {
+    // The id of every node must be its position in `nodes`, documents are only built by the parser
+    pub(crate) fn new(declaration: Option<XmlDeclaration>, doctype: Option<Doctype>, nodes: Vec<Node>) -> Document {
+        let top_level = nodes.iter().filter(|node| node.get_parent().is_none()).map(Node::get_id).collect();
+        let ids = NodeIdAllocator::new(nodes.len());
+        Document { declaration, doctype, nodes: nodes.into_iter().map(Some).collect(), top_level, ids }
+    }
+
+    pub fn get_declaration(&self) -> Option<&XmlDeclaration> {
+        self.declaration.as_ref()
+    }
+
+    pub fn get_doctype(&self) -> Option<&Doctype> {
+        self.doctype.as_ref()
+    }
+
+    // The root element, None when the document has none
+    pub fn root(&self) -> Option<NodeId> {
+        self.top_level.iter().copied().find(|&id| self.get(id).is_some_and(Node::is_element))
+    }
+
+    // None when there is no such node or it has been removed
+    pub fn get(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(id.get_index())?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.nodes.get_mut(id.get_index())?.as_mut()
+    }
+
+    // Every node in the order it was created, which is document order for a parsed document
+    pub fn node_ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.nodes.iter().flatten().map(Node::get_id)
+    }
+
+    // The root element with the comments and processing instructions around it, in document order
+    pub fn get_top_level_nodes(&self) -> Vec<NodeId> {
+        self.top_level.clone()
+    }
+
+    // Every node in the order it was created, see node_ids
+    pub fn get_nodes(&self) -> Vec<&Node> {
+        self.nodes.iter().flatten().collect()
+    }
+
+    // Every comment, including the ones outside the root element
+    pub fn get_comments(&self) -> Vec<&Node> {
+        self.nodes.iter().flatten().filter(|node| node.is_comment()).collect()
+    }
+
+    pub fn get_processing_instructions(&self) -> Vec<&Node> {
+        self.nodes.iter().flatten().filter(|node| node.is_processing_instruction()).collect()
+    }
+
+    // The text of the node and of all its descendants, in document order
+    pub fn text_content(&self, id: NodeId) -> String {
+        let Some(node) = self.get(id) else {
+            return String::new();
+        };
+        if !node.is_element() {
+            return if node.is_text() { node.get_inner_element().clone() } else { String::new() };
+        }
+        self.descendants(id)
+            .of_kind(NodeKind::Text)
+            .filter_map(|descendant| self.get(descendant))
+            .map(|descendant| descendant.get_inner_element().as_str())
+            .collect()
+    }
+
+    // Elements matched by expanded name, whatever prefix they were written with
+    pub fn get_elements_ns(&self, namespace: Option<&str>, local_name: &str) -> Vec<&Node> {
+        self.nodes.iter().flatten()
+            .filter(|node| node.is_element() && node.get_namespace_uri().map(String::as_str) == namespace && node.get_local_name() == local_name)
+            .collect()
+    }
+
+    pub fn into_nodes(self) -> Vec<Node> {
+        self.nodes.into_iter().flatten().collect()
+    }
+}
+
+// EncName from the XML grammar: [A-Za-z] ([A-Za-z0-9._] | '-')*
+fn is_encoding_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &str) -> Result<XmlDeclaration, XmlError> {
+        XmlDeclaration::parse(data, Position::default())
+    }
+
+    #[test]
+    fn test_full_declaration() {
+        let declaration = parse("version=\"1.0\" encoding='UTF-8' standalone=\"yes\"").unwrap();
+        assert_eq!(declaration.get_version(), "1.0");
+        assert_eq!(declaration.get_encoding().unwrap(), "UTF-8");
+        assert_eq!(declaration.get_standalone(), Some(true));
+        assert_eq!(declaration.to_string(), "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>");
+    }
+
+    #[test]
+    fn test_version_only() {
+        let declaration = parse("version = \"1.1\"").unwrap();
+        assert_eq!(declaration.get_version(), "1.1");
+        assert_eq!(declaration.get_encoding(), None);
+        assert_eq!(declaration.get_standalone(), None);
+    }
+
+    #[test]
+    fn test_invalid_declarations() {
+        let error = parse("version=\"2.0\"").unwrap_err();
+        assert_eq!(error.get_position().unwrap().column, 10);
+        assert!(parse("version=\"1.0\" standalone=\"maybe\"").is_err());
+        assert!(parse("encoding=\"UTF-8\"").is_err());
+        assert!(parse("version=\"1.0\" standalone=\"no\" encoding=\"UTF-8\"").is_err());
+        assert!(parse("version=\"1.0\" encoding=\"UTF 8\"").is_err());
+        assert!(parse("version=\"1.0\" author=\"me\"").is_err());
+        assert!(parse("version=1.0").is_err());
+        assert!(parse("").is_err());
+    }
+}
@@ -0,0 +1,275 @@
+use std::fmt;
+use crate::error::XmlError;
+
+// The encodings the reader can turn into a String
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    Windows1252,
+}
+
+impl Encoding {
+    // Looks up the name used in encoding="...", names are compared case-insensitively
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" | "us-ascii" | "ascii" => Some(Encoding::Utf8),
+            "utf-16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf-16" => Some(Encoding::Utf16Be),
+            "iso-8859-1" | "iso8859-1" | "latin1" | "l1" => Some(Encoding::Latin1),
+            "windows-1252" | "cp1252" => Some(Encoding::Windows1252),
+            _ => None,
+        }
+    }
+
+    fn is_utf16(self) -> bool {
+        matches!(self, Encoding::Utf16Le | Encoding::Utf16Be)
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Latin1 => "ISO-8859-1",
+            Encoding::Windows1252 => "windows-1252",
+        };
+        write!(f, "{name}")
+    }
+}
+
+// Works out the encoding of a file from its byte order mark, the first bytes of "<?xml" or the
+// encoding pseudo-attribute, in that order. Returns the encoding and the length of the byte order mark.
+pub fn detect_encoding(bytes: &[u8]) -> Result<(Encoding, usize), XmlError> {
+    match bytes {
+        [0xEF, 0xBB, 0xBF, ..] => return Ok((Encoding::Utf8, 3)),
+        [0x00, 0x00, 0xFE, 0xFF, ..] | [0xFF, 0xFE, 0x00, 0x00, ..] => {
+            return Err(XmlError::UnsupportedEncoding { name: "UTF-32".to_string() });
+        }
+        [0xFF, 0xFE, ..] => return Ok((Encoding::Utf16Le, 2)),
+        [0xFE, 0xFF, ..] => return Ok((Encoding::Utf16Be, 2)),
+        [b'<', 0x00, b'?', 0x00, ..] => return Ok((Encoding::Utf16Le, 0)),
+        [0x00, b'<', 0x00, b'?', ..] => return Ok((Encoding::Utf16Be, 0)),
+        _ => (),
+    }
+
+    let Some(name) = declared_encoding(bytes) else {
+        return Ok((Encoding::Utf8, 0));
+    };
+    match Encoding::from_name(&name) {
+        // A declaration readable as single bytes means the file is not UTF-16
+        Some(encoding) if encoding.is_utf16() => Err(XmlError::UnsupportedEncoding {
+            name: format!("{name} without a byte order mark"),
+        }),
+        Some(encoding) => Ok((encoding, 0)),
+        None => Err(XmlError::UnsupportedEncoding { name }),
+    }
+}
+
+// Turns the bytes of an XML file into a String, the byte order mark is removed
+pub fn decode_bytes(bytes: &[u8]) -> Result<String, XmlError> {
+    let (encoding, bom_length) = detect_encoding(bytes)?;
+    let mut text = String::with_capacity(bytes.len());
+    Decoder::new(encoding, bom_length).decode(&bytes[bom_length..], true, &mut text)?;
+    Ok(text)
+}
+
+// Decodes input that arrives in pieces, a character split between two pieces is kept until the rest arrives
+#[derive(Debug)]
+pub struct Decoder {
+    encoding: Encoding,
+    pending: Vec<u8>,
+    // Bytes of the input before `pending`, used in error messages
+    offset: usize,
+}
+
+impl Decoder {
+    // `offset` is where the first byte given to decode sits in the input, the length of the byte order mark
+    pub fn new(encoding: Encoding, offset: usize) -> Decoder {
+        Decoder { encoding, pending: Vec::new(), offset }
+    }
+
+    pub fn get_encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    // Appends the decoded `bytes` to `output`, `last` tells that no more input follows
+    pub fn decode(&mut self, bytes: &[u8], last: bool, output: &mut String) -> Result<(), XmlError> {
+        let mut joined;
+        let input = if self.pending.is_empty() {
+            bytes
+        } else {
+            joined = std::mem::take(&mut self.pending);
+            joined.extend_from_slice(bytes);
+            &joined
+        };
+        let used = match self.encoding {
+            Encoding::Utf8 => self.decode_utf8(input, last, output)?,
+            Encoding::Utf16Le | Encoding::Utf16Be => self.decode_utf16(input, last, output)?,
+            Encoding::Latin1 => {
+                output.extend(input.iter().map(|&byte| byte as char));
+                input.len()
+            }
+            Encoding::Windows1252 => {
+                output.extend(input.iter().map(|&byte| windows_1252_char(byte)));
+                input.len()
+            }
+        };
+        self.pending = input[used..].to_vec();
+        self.offset += used;
+        Ok(())
+    }
+
+    fn malformed(&self, offset: usize) -> XmlError {
+        XmlError::MalformedEncoding { encoding: self.encoding.to_string(), offset: self.offset + offset }
+    }
+
+    // Returns how many bytes were decoded, an incomplete character at the end is left for the next call
+    fn decode_utf8(&self, input: &[u8], last: bool, output: &mut String) -> Result<usize, XmlError> {
+        match std::str::from_utf8(input) {
+            Ok(text) => {
+                output.push_str(text);
+                Ok(input.len())
+            }
+            Err(error) => {
+                let valid = error.valid_up_to();
+                if error.error_len().is_some() || last {
+                    return Err(self.malformed(valid));
+                }
+                output.push_str(std::str::from_utf8(&input[..valid]).unwrap_or_default());
+                Ok(valid)
+            }
+        }
+    }
+
+    fn decode_utf16(&self, input: &[u8], last: bool, output: &mut String) -> Result<usize, XmlError> {
+        if last && !input.len().is_multiple_of(2) {
+            return Err(self.malformed(input.len() - 1));
+        }
+        let mut units: Vec<u16> = input.chunks_exact(2).map(|pair| match self.encoding {
+            Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+            _ => u16::from_be_bytes([pair[0], pair[1]]),
+        }).collect();
+        // A high surrogate at the end waits for its partner
+        if !last && units.last().is_some_and(|unit| (0xD800..0xDC00).contains(unit)) {
+            units.pop();
+        }
+
+        let mut used = 0;
+        for result in char::decode_utf16(units) {
+            match result {
+                Ok(character) => {
+                    output.push(character);
+                    used += character.len_utf16() * 2;
+                }
+                Err(_) => return Err(self.malformed(used)),
+            }
+        }
+        Ok(used)
+    }
+}
+
+// The value of encoding="..." in an XML declaration written in single bytes
+fn declared_encoding(bytes: &[u8]) -> Option<String> {
+    if !bytes.starts_with(b"<?xml") {
+        return None;
+    }
+    let end = bytes.windows(2).position(|pair| pair == b"?>")?;
+    let declaration: String = bytes[..end].iter().map(|&byte| byte as char).collect();
+    let rest = &declaration[declaration.find("encoding")? + "encoding".len()..];
+    let rest = rest.trim_start().strip_prefix('=')?.trim_start();
+    let quote = rest.chars().next().filter(|&c| c == '"' || c == '\'')?;
+    let value = &rest[1..];
+    Some(value[..value.find(quote)?].to_string())
+}
+
+// windows-1252 is ISO-8859-1 with printable characters in 0x80..0x9F, the five unused bytes keep their
+// ISO-8859-1 meaning
+fn windows_1252_char(byte: u8) -> char {
+    const HIGH: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}',
+        '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+    ];
+    match byte {
+        0x80..=0x9F => HIGH[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str, little_endian: bool, bom: bool) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        if bom {
+            bytes.extend(if little_endian { [0xFF, 0xFE] } else { [0xFE, 0xFF] });
+        }
+        for unit in text.encode_utf16() {
+            bytes.extend(if little_endian { unit.to_le_bytes() } else { unit.to_be_bytes() });
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_utf8_with_and_without_bom() {
+        assert_eq!(decode_bytes("<a>é</a>".as_bytes()).unwrap(), "<a>é</a>");
+        let mut bytes = vec![0xEF, 0xBB, 0xBF];
+        bytes.extend(b"<?xml version=\"1.0\"?><a/>");
+        assert_eq!(decode_bytes(&bytes).unwrap(), "<?xml version=\"1.0\"?><a/>");
+    }
+
+    #[test]
+    fn test_utf16() {
+        let text = "<?xml version=\"1.0\" encoding=\"UTF-16\"?>\r\n<a>Grüße 😀</a>";
+        assert_eq!(decode_bytes(&utf16(text, true, true)).unwrap(), text);
+        assert_eq!(decode_bytes(&utf16(text, false, true)).unwrap(), text);
+        assert_eq!(decode_bytes(&utf16(text, true, false)).unwrap(), text);
+        assert_eq!(detect_encoding(&utf16(text, false, false)).unwrap(), (Encoding::Utf16Be, 0));
+    }
+
+    #[test]
+    fn test_single_byte_encodings() {
+        let mut bytes = b"<?xml version='1.0' encoding='ISO-8859-1'?><a>".to_vec();
+        bytes.extend([0xE9, 0x80, b'<', b'/', b'a', b'>']);
+        assert_eq!(decode_bytes(&bytes).unwrap(), "<?xml version='1.0' encoding='ISO-8859-1'?><a>é\u{80}</a>");
+
+        let mut bytes = b"<?xml version=\"1.0\" encoding=\"windows-1252\"?><a>".to_vec();
+        bytes.extend([0x80, 0x93, 0xE9, 0x94, b'<', b'/', b'a', b'>']);
+        assert_eq!(decode_bytes(&bytes).unwrap(), "<?xml version=\"1.0\" encoding=\"windows-1252\"?><a>€“é”</a>");
+    }
+
+    #[test]
+    fn test_decoding_in_pieces() {
+        let text = "<a>Grüße 😀 €</a>";
+        for (encoding, bytes) in [(Encoding::Utf8, text.as_bytes().to_vec()), (Encoding::Utf16Be, utf16(text, false, false))] {
+            let mut decoder = Decoder::new(encoding, 0);
+            let mut output = String::new();
+            for byte in &bytes {
+                decoder.decode(std::slice::from_ref(byte), false, &mut output).unwrap();
+            }
+            decoder.decode(&[], true, &mut output).unwrap();
+            assert_eq!(output, text);
+        }
+
+        let mut decoder = Decoder::new(Encoding::Utf8, 3);
+        let mut output = String::new();
+        decoder.decode(&[b'a', 0xC3], false, &mut output).unwrap();
+        assert_eq!(decoder.decode(&[], true, &mut output).unwrap_err(), XmlError::MalformedEncoding { encoding: "UTF-8".to_string(), offset: 4 });
+    }
+
+    #[test]
+    fn test_encoding_errors() {
+        assert_eq!(decode_bytes(b"<?xml version=\"1.0\" encoding=\"EBCDIC\"?><a/>").unwrap_err(), XmlError::UnsupportedEncoding { name: "EBCDIC".to_string() });
+        assert!(matches!(decode_bytes(b"<?xml version=\"1.0\" encoding=\"UTF-16\"?><a/>"), Err(XmlError::UnsupportedEncoding { .. })));
+        assert!(matches!(decode_bytes(&[0xFF, 0xFE, 0x00, 0x00]), Err(XmlError::UnsupportedEncoding { .. })));
+        assert_eq!(decode_bytes(&[b'<', b'a', b'>', 0xE9, b'<']).unwrap_err(), XmlError::MalformedEncoding { encoding: "UTF-8".to_string(), offset: 3 });
+        assert_eq!(decode_bytes(&[0xFF, 0xFE, b'<', 0x00, 0x00, 0xD8, b'a', 0x00]).unwrap_err(), XmlError::MalformedEncoding { encoding: "UTF-16LE".to_string(), offset: 4 });
+        assert!(matches!(decode_bytes(&[0xFE, 0xFF, 0x00]), Err(XmlError::MalformedEncoding { .. })));
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use crate::error::{Limit, XmlError};
+use crate::options::Limits;
+use crate::tokenizer::{is_xml_char, Position};
+
+// The five entities every XML document may use without declaring them
+pub fn predefined_entity(name: &str) -> Option<char> {
+    match name {
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "amp" => Some('&'),
+        "apos" => Some('\''),
+        "quot" => Some('"'),
+        _ => None,
+    }
+}
+
+// Value of a `&#...;` reference, `reference` is the part between "&#" and ";"
+pub fn character_reference(reference: &str) -> Option<char> {
+    let code = match reference.strip_prefix('x') {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => reference.parse::<u32>().ok()?,
+    };
+    char::from_u32(code).filter(|&c| is_xml_char(c))
+}
+
+// Replaces entity and character references in text or attribute values.
+// `start` is where `text` begins in the document and is used to locate errors.
+// Unknown or malformed references are kept as written unless `strict` is set.
+pub fn decode_entities(text: &str, start: Position, strict: bool) -> Result<String, XmlError> {
+    EntityDecoder::new(HashMap::new(), strict).decode(text, start)
+}
+
+// Decodes references using the entities declared in the document type declaration as well.
+// The replacement text of a declared entity is decoded again, so entities may refer to each other.
+// It is inserted as character data, markup in it is not parsed: strict mode rejects a replacement
+// text that contains '<', the lenient decoder keeps it as literal text.
+// The expansion size is counted over every call, one decoder is meant to serve one document.
+pub struct EntityDecoder {
+    entities: HashMap<String, String>,
+    strict: bool,
+    limits: Limits,
+    expanded: usize,
+}
+
+impl EntityDecoder {
+    pub fn new(entities: HashMap<String, String>, strict: bool) -> EntityDecoder {
+        EntityDecoder::with_limits(entities, strict, Limits::default())
+    }
+
+    pub fn with_limits(entities: HashMap<String, String>, strict: bool, limits: Limits) -> EntityDecoder {
+        EntityDecoder { entities, strict, limits, expanded: 0 }
+    }
+
+    // Bytes of replacement text produced by declared entities so far
+    pub fn get_expanded_size(&self) -> usize {
+        self.expanded
+    }
+
+    pub fn decode(&mut self, text: &str, start: Position) -> Result<String, XmlError> {
+        if !text.contains('&') {
+            return Ok(text.to_string());
+        }
+        let mut result = String::with_capacity(text.len());
+        self.decode_into(text, start, None, &mut Vec::new(), &mut result)?;
+        Ok(result)
+    }
+
+    // Errors inside replacement text are reported at the reference that was being expanded
+    fn decode_into(&mut self, text: &str, start: Position, reference: Option<Position>, expanding: &mut Vec<String>, result: &mut String) -> Result<(), XmlError> {
+        let mut rest = text;
+
+        while let Some(amp_i) = rest.find('&') {
+            result.push_str(&rest[..amp_i]);
+            let position = reference.unwrap_or_else(|| start.advanced_by(&text[..text.len() - rest.len() + amp_i]));
+            rest = &rest[amp_i..];
+
+            let semicolon_i = match rest.find(';') {
+                Some(index) if !rest[1..index].contains(['&', ' ', '\t', '\n', '\r', '<']) => index,
+                _ => {
+                    if self.strict {
+                        return Err(XmlError::InvalidReference { reference: "&".to_string(), position });
+                    }
+                    result.push('&');
+                    rest = &rest[1..];
+                    continue;
+                }
+            };
+            let name = &rest[1..semicolon_i];
+            let written = &rest[..=semicolon_i];
+            rest = &rest[semicolon_i + 1..];
+
+            let decoded = if let Some(reference) = name.strip_prefix('#') {
+                character_reference(reference).ok_or_else(|| XmlError::InvalidReference { reference: written.to_string(), position })
+            } else if let Some(c) = predefined_entity(name) {
+                Ok(c)
+            } else if let Some(value) = self.entities.get(name) {
+                if expanding.iter().any(|open| open == name) {
+                    if self.strict {
+                        return Err(XmlError::RecursiveEntity { name: name.to_string(), position });
+                    }
+                    result.push_str(written);
+                    continue;
+                }
+                if self.strict && value.contains('<') {
+                    return Err(XmlError::InvalidSyntax {
+                        message: format!("The replacement text of &{name}; contains markup, which is not supported"),
+                        position,
+                    });
+                }
+                if expanding.len() >= self.limits.max_entity_depth {
+                    return Err(XmlError::LimitExceeded { limit: Limit::EntityDepth, maximum: self.limits.max_entity_depth, position });
+                }
+                self.expanded = self.expanded.saturating_add(value.len());
+                if self.expanded > self.limits.max_entity_expansion_size {
+                    return Err(XmlError::LimitExceeded {
+                        limit: Limit::EntityExpansionSize,
+                        maximum: self.limits.max_entity_expansion_size,
+                        position,
+                    });
+                }
+                let value = value.clone();
+                expanding.push(name.to_string());
+                self.decode_into(&value, start, Some(position), expanding, result)?;
+                expanding.pop();
+                continue;
+            } else {
+                Err(XmlError::UndefinedEntity { name: name.to_string(), position })
+            };
+            match decoded {
+                Ok(c) => result.push(c),
+                Err(error) if self.strict => return Err(error),
+                Err(_) => result.push_str(written),
+            }
+        }
+        result.push_str(rest);
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_predefined_entities() {
+        let decoded = decode_entities("&lt; &gt; &amp; &apos; &quot;", Position::default(), true).unwrap();
+        assert_eq!(decoded, "< > & ' \"");
+    }
+
+    #[test]
+    fn test_character_references() {
+        let decoded = decode_entities("&#169; 2024 &#x1F600;&#65;", Position::default(), true).unwrap();
+        assert_eq!(decoded, "© 2024 😀A");
+        assert_eq!(character_reference("xD800"), None);
+        assert_eq!(character_reference("0"), None);
+        assert_eq!(character_reference("X41"), None);
+    }
+
+    #[test]
+    fn test_double_escaping_is_decoded_once() {
+        let decoded = decode_entities("&amp;lt;", Position::default(), true).unwrap();
+        assert_eq!(decoded, "&lt;");
+    }
+
+    #[test]
+    fn test_lenient_keeps_unknown_references() {
+        let decoded = decode_entities("AT&T &copy; &#xZZ; &lt;", Position::default(), false).unwrap();
+        assert_eq!(decoded, "AT&T &copy; &#xZZ; <");
+    }
+
+    #[test]
+    fn test_strict_errors() {
+        let start = Position { line: 3, column: 5, offset: 40 };
+        assert_eq!(decode_entities("a\nb &copy;", start, true).unwrap_err(), XmlError::UndefinedEntity {
+            name: "copy".to_string(),
+            position: Position { line: 4, column: 3, offset: 44 },
+        });
+        assert!(matches!(decode_entities("AT&T", start, true), Err(XmlError::InvalidReference { .. })));
+        assert!(matches!(decode_entities("&#xD800;", start, true), Err(XmlError::InvalidReference { .. })));
+    }
+
+    fn declared_entities() -> HashMap<String, String> {
+        let mut entities = HashMap::new();
+        entities.insert("company".to_string(), "Acme &amp; Sons".to_string());
+        entities.insert("signature".to_string(), "&company; &#169; 2024".to_string());
+        entities.insert("loop".to_string(), "again &loop;".to_string());
+        entities.insert("broken".to_string(), "&missing;".to_string());
+        entities.insert("bold".to_string(), "<b>bold</b>".to_string());
+        entities
+    }
+
+    #[test]
+    fn test_declared_entities() {
+        let entities = declared_entities();
+        let mut decoder = EntityDecoder::new(entities, true);
+        assert_eq!(decoder.decode("By &company;", Position::default()).unwrap(), "By Acme & Sons");
+        assert_eq!(decoder.decode("&signature;", Position::default()).unwrap(), "Acme & Sons © 2024");
+    }
+
+    #[test]
+    fn test_markup_in_replacement_text() {
+        let start = Position { line: 1, column: 4, offset: 3 };
+        let error = EntityDecoder::new(declared_entities(), true).decode("x &bold;", start).unwrap_err();
+        assert!(matches!(error, XmlError::InvalidSyntax { position: Position { offset: 5, .. }, .. }));
+        let decoded = EntityDecoder::new(declared_entities(), false).decode("&bold;", start).unwrap();
+        assert_eq!(decoded, "<b>bold</b>");
+    }
+
+    #[test]
+    fn test_recursive_entity() {
+        let entities = declared_entities();
+        let start = Position { line: 2, column: 1, offset: 10 };
+        let error = EntityDecoder::new(entities.clone(), true).decode("x &loop;", start).unwrap_err();
+        assert_eq!(error, XmlError::RecursiveEntity {
+            name: "loop".to_string(),
+            position: Position { line: 2, column: 3, offset: 12 },
+        });
+        let decoded = EntityDecoder::new(entities, false).decode("&loop;", start).unwrap();
+        assert_eq!(decoded, "again &loop;");
+    }
+
+    #[test]
+    fn test_error_inside_replacement_text() {
+        let entities = declared_entities();
+        let start = Position { line: 4, column: 7, offset: 30 };
+        let error = EntityDecoder::new(entities, true).decode("&broken;", start).unwrap_err();
+        assert_eq!(error.get_position(), Some(start));
+    }
+
+    // The classic "billion laughs" document, each level refers ten times to the one below
+    fn billion_laughs() -> HashMap<String, String> {
+        let mut entities = HashMap::new();
+        entities.insert("lol0".to_string(), "lol".to_string());
+        for level in 1..10 {
+            entities.insert(format!("lol{level}"), format!("&lol{};", level - 1).repeat(10));
+        }
+        entities
+    }
+
+    #[test]
+    fn test_billion_laughs() {
+        let mut decoder = EntityDecoder::new(billion_laughs(), false);
+        let error = decoder.decode("&lol9;", Position::default()).unwrap_err();
+        assert!(matches!(error, XmlError::LimitExceeded { limit: Limit::EntityExpansionSize, .. }));
+        assert!(decoder.get_expanded_size() <= Limits::default().max_entity_expansion_size + 100);
+    }
+
+    #[test]
+    fn test_expansion_size_counts_over_calls() {
+        let limits = Limits { max_entity_expansion_size: 20, ..Limits::default() };
+        let mut decoder = EntityDecoder::with_limits(declared_entities(), true, limits);
+        assert!(decoder.decode("&company;", Position::default()).is_ok());
+        assert!(matches!(decoder.decode("&company;", Position::default()), Err(XmlError::LimitExceeded { .. })));
+    }
+
+    #[test]
+    fn test_entity_depth() {
+        let limits = Limits { max_entity_depth: 3, ..Limits::default() };
+        let mut decoder = EntityDecoder::with_limits(billion_laughs(), true, limits);
+        assert!(decoder.decode("&lol2;", Position::default()).is_ok());
+        let error = decoder.decode("&lol3;", Position::default()).unwrap_err();
+        assert!(matches!(error, XmlError::LimitExceeded { limit: Limit::EntityDepth, maximum: 3, .. }));
+    }
+}
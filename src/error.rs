@@ -0,0 +1,148 @@
+use std::{fmt, io};
+use crate::tokenizer::Position;
+
+// The entry of the Limits that was exceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    EntityExpansionSize,
+    EntityDepth,
+    ElementDepth,
+    Attributes,
+    NameLength,
+    DocumentSize,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Limit::EntityExpansionSize => "entity expansion size",
+            Limit::EntityDepth => "entity nesting depth",
+            Limit::ElementDepth => "element nesting depth",
+            Limit::Attributes => "number of attributes",
+            Limit::NameLength => "name length",
+            Limit::DocumentSize => "document size",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum XmlError {
+    Io { kind: io::ErrorKind, message: String },
+    UnsupportedEncoding { name: String },
+    MalformedEncoding { encoding: String, offset: usize },
+    UnexpectedEof { expected: String, position: Position },
+    MismatchedEndTag { expected: String, found: String, position: Position },
+    InvalidAttribute { message: String, position: Position },
+    InvalidCharacter { character: char, position: Position },
+    InvalidSyntax { message: String, position: Position },
+    InvalidReference { reference: String, position: Position },
+    UndefinedEntity { name: String, position: Position },
+    RecursiveEntity { name: String, position: Position },
+    UndeclaredPrefix { prefix: String, position: Position },
+    LimitExceeded { limit: Limit, maximum: usize, position: Position },
+    MissingAttribute { name: String },
+    // A change to a Document that would break the tree, for example a node becoming its own ancestor
+    InvalidOperation { message: String },
+}
+
+impl XmlError {
+    // Where in the input the error was found, I/O, encoding and lookup errors have no position
+    pub fn get_position(&self) -> Option<Position> {
+        match self {
+            XmlError::UnexpectedEof { position, .. }
+            | XmlError::MismatchedEndTag { position, .. }
+            | XmlError::InvalidAttribute { position, .. }
+            | XmlError::InvalidCharacter { position, .. }
+            | XmlError::InvalidSyntax { position, .. }
+            | XmlError::InvalidReference { position, .. }
+            | XmlError::UndefinedEntity { position, .. }
+            | XmlError::RecursiveEntity { position, .. }
+            | XmlError::UndeclaredPrefix { position, .. }
+            | XmlError::LimitExceeded { position, .. } => Some(*position),
+            XmlError::Io { .. }
+            | XmlError::UnsupportedEncoding { .. }
+            | XmlError::MalformedEncoding { .. }
+            | XmlError::MissingAttribute { .. }
+            | XmlError::InvalidOperation { .. } => None,
+        }
+    }
+
+    pub fn get_position_mut(&mut self) -> Option<&mut Position> {
+        match self {
+            XmlError::UnexpectedEof { position, .. }
+            | XmlError::MismatchedEndTag { position, .. }
+            | XmlError::InvalidAttribute { position, .. }
+            | XmlError::InvalidCharacter { position, .. }
+            | XmlError::InvalidSyntax { position, .. }
+            | XmlError::InvalidReference { position, .. }
+            | XmlError::UndefinedEntity { position, .. }
+            | XmlError::RecursiveEntity { position, .. }
+            | XmlError::UndeclaredPrefix { position, .. }
+            | XmlError::LimitExceeded { position, .. } => Some(position),
+            XmlError::Io { .. }
+            | XmlError::UnsupportedEncoding { .. }
+            | XmlError::MalformedEncoding { .. }
+            | XmlError::MissingAttribute { .. }
+            | XmlError::InvalidOperation { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XmlError::Io { message, .. } => write!(f, "I/O error: {message}")?,
+            XmlError::UnsupportedEncoding { name } => write!(f, "Unsupported encoding {name}")?,
+            XmlError::MalformedEncoding { encoding, offset } => write!(f, "Input is not valid {encoding} at byte {offset}")?,
+            XmlError::UnexpectedEof { expected, .. } => write!(f, "Unexpected end of input, expected {expected}")?,
+            XmlError::MismatchedEndTag { expected, found, .. } => write!(f, "Mismatched end tag </{found}>, expected </{expected}>")?,
+            XmlError::InvalidAttribute { message, .. } => write!(f, "Invalid attribute: {message}")?,
+            XmlError::InvalidCharacter { character, .. } => write!(f, "Invalid character {character:?}")?,
+            XmlError::InvalidSyntax { message, .. } => write!(f, "{message}")?,
+            XmlError::InvalidReference { reference, .. } => write!(f, "Invalid reference {reference}")?,
+            XmlError::UndefinedEntity { name, .. } => write!(f, "Undefined entity &{name};")?,
+            XmlError::RecursiveEntity { name, .. } => write!(f, "Entity &{name}; refers to itself")?,
+            XmlError::UndeclaredPrefix { prefix, .. } => write!(f, "Namespace prefix {prefix} is not declared")?,
+            XmlError::LimitExceeded { limit, maximum, .. } => write!(f, "Maximum {limit} of {maximum} exceeded")?,
+            XmlError::MissingAttribute { name } => write!(f, "No such key: {name} present")?,
+            XmlError::InvalidOperation { message } => write!(f, "Invalid operation: {message}")?,
+        }
+        match self.get_position() {
+            Some(position) => write!(f, " at line {}, column {}", position.line, position.column),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for XmlError {}
+
+impl From<io::Error> for XmlError {
+    fn from(error: io::Error) -> XmlError {
+        XmlError::Io { kind: error.kind(), message: error.to_string() }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_with_position() {
+        let error = XmlError::MismatchedEndTag {
+            expected: "a".to_string(),
+            found: "b".to_string(),
+            position: Position { line: 3, column: 5, offset: 20 },
+        };
+        assert_eq!(error.to_string(), "Mismatched end tag </b>, expected </a> at line 3, column 5");
+        assert_eq!(error.get_position().unwrap().offset, 20);
+    }
+
+    #[test]
+    fn test_io_error_conversion() {
+        let error: XmlError = io::Error::new(io::ErrorKind::NotFound, "missing").into();
+        assert_eq!(error, XmlError::Io { kind: io::ErrorKind::NotFound, message: "missing".to_string() });
+        assert!(error.get_position().is_none());
+    }
+}
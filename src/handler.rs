@@ -0,0 +1,125 @@
+use std::io::BufRead;
+use crate::attributes::Attributes;
+use crate::doctype::Doctype;
+use crate::document::XmlDeclaration;
+use crate::error::XmlError;
+use crate::namespaces::NamespaceScope;
+use crate::reader::{XmlEvent, XmlReader};
+use crate::tokenizer::Position;
+
+// Callbacks driven by parse_with_handler, every callback does nothing unless it is overridden.
+// Useful to pick data out of a document without building Nodes for all of it.
+pub trait ContentHandler {
+    fn declaration(&mut self, _declaration: &XmlDeclaration, _position: Position) {}
+
+    fn doctype(&mut self, _doctype: &Doctype, _position: Position) {}
+
+    // `namespace` is the resolved namespace of the element, `namespaces` the bindings in scope on it
+    fn start_element(&mut self, _name: &str, _attributes: &Attributes, _namespace: Option<&str>, _namespaces: &NamespaceScope, _position: Position) {}
+
+    // Also called for <empty/> elements, right after start_element
+    fn end_element(&mut self, _name: &str, _position: Position) {}
+
+    // Decoded character data, whitespace is handled as set in the ParseOptions
+    fn characters(&mut self, _text: &str, _position: Position) {}
+
+    // The content of a CDATA section, passed on to characters by default
+    fn cdata(&mut self, text: &str, position: Position) {
+        self.characters(text, position);
+    }
+
+    fn comment(&mut self, _comment: &str, _position: Position) {}
+
+    fn processing_instruction(&mut self, _target: &str, _data: &str, _position: Position) {}
+
+    // Called with the error that stops the parse, parse_with_handler returns it afterwards
+    fn error(&mut self, _error: &XmlError) {}
+}
+
+// Reads the whole input and passes every event to `handler`
+pub fn parse_with_handler<R: BufRead, H: ContentHandler + ?Sized>(reader: XmlReader<R>, handler: &mut H) -> Result<(), XmlError> {
+    for event in reader {
+        let event = match event {
+            Ok(event) => event,
+            Err(error) => {
+                handler.error(&error);
+                return Err(error);
+            }
+        };
+        match event {
+            XmlEvent::Declaration { declaration, position } => handler.declaration(&declaration, position),
+            XmlEvent::Doctype { doctype, position } => handler.doctype(&doctype, position),
+            XmlEvent::StartElement { name, attributes, namespace, namespaces, position } => {
+                handler.start_element(&name, &attributes, namespace.as_deref(), &namespaces, position);
+            }
+            XmlEvent::EndElement { name, position, .. } => handler.end_element(&name, position),
+            XmlEvent::Text { text, position, .. } => handler.characters(&text, position),
+            XmlEvent::CData { text, position } => handler.cdata(&text, position),
+            XmlEvent::Comment { comment, position } => handler.comment(&comment, position),
+            XmlEvent::ProcessingInstruction { target, data, position } => handler.processing_instruction(&target, &data, position),
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::options::ParseOptions;
+    use super::*;
+
+    // Collects the text of every <price> element, the way a custom extractor would
+    #[derive(Default)]
+    struct PriceExtractor {
+        in_price: bool,
+        prices: Vec<String>,
+        comments: usize,
+        errors: usize,
+    }
+
+    impl ContentHandler for PriceExtractor {
+        fn start_element(&mut self, name: &str, _attributes: &Attributes, _namespace: Option<&str>, _namespaces: &NamespaceScope, _position: Position) {
+            if name == "price" {
+                self.in_price = true;
+                self.prices.push(String::new());
+            }
+        }
+
+        fn end_element(&mut self, name: &str, _position: Position) {
+            self.in_price &= name != "price";
+        }
+
+        fn characters(&mut self, text: &str, _position: Position) {
+            if let (true, Some(price)) = (self.in_price, self.prices.last_mut()) {
+                price.push_str(text);
+            }
+        }
+
+        fn comment(&mut self, _comment: &str, _position: Position) {
+            self.comments += 1;
+        }
+
+        fn error(&mut self, _error: &XmlError) {
+            self.errors += 1;
+        }
+    }
+
+    #[test]
+    fn test_extract_with_handler() {
+        let xml = "<shop>\n  <!-- prices in euro -->\n  <item><price>1.50</price></item>\n  <item><price>2<![CDATA[.25]]></price><price/></item>\n</shop>";
+        let mut extractor = PriceExtractor::default();
+        parse_with_handler(XmlReader::from_str(xml, ParseOptions::strict()), &mut extractor).unwrap();
+        assert_eq!(extractor.prices, vec!["1.50", "2.25", ""]);
+        assert_eq!(extractor.comments, 1);
+        assert_eq!(extractor.errors, 0);
+    }
+
+    #[test]
+    fn test_handler_sees_the_error() {
+        let mut extractor = PriceExtractor::default();
+        let result = parse_with_handler(XmlReader::from_str("<shop><price>1</shop>", ParseOptions::strict()), &mut extractor);
+        assert!(matches!(result, Err(XmlError::MismatchedEndTag { .. })));
+        assert_eq!(extractor.errors, 1);
+        assert_eq!(extractor.prices, vec!["1"]);
+    }
+}
@@ -1,2 +1,15 @@
+pub mod attributes;
+pub mod doctype;
+pub mod document;
+pub mod encoding;
+pub mod entities;
+pub mod error;
+pub mod handler;
+pub mod mutation;
+pub mod namespaces;
+pub mod navigation;
+pub mod options;
+pub mod reader;
+pub mod tokenizer;
 pub mod tree_struct;
 pub mod xml_proc;
\ No newline at end of file
@@ -1,11 +1,9 @@
-use std::collections::HashMap;
 use std::env;
 use std::io::{stdin,stdout,Write};
-use tree_struct::Node;
-use xml_proc::*;
-
-mod xml_proc;
-mod tree_struct;
+use xml_proc::document::Document;
+use xml_proc::options::ParseOptions;
+use xml_proc::tree_struct::NodeId;
+use xml_proc::xml_proc::*;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
@@ -14,48 +12,54 @@ fn main() {
 
     match file_contents {
         Ok(contents) => {
-            process_file(&contents, file_directory);
+            process_file(&contents, &file_directory);
         },
-        Err(string_error) => {
-            println!("File could not be found or read: {}", file_directory);
+        Err(error) => {
+            println!("File could not be found or read: {} ({})", file_directory, error);
         }
     }
 
 }
 
-fn process_file(contents : &String, file_directory : String){
-    let list_lines: Vec<String> = contents.lines().map(|line| line.to_string()).collect();
-    let all_nodes = process_line_list(&list_lines);
-    let tree_node = tree_id_to_node(&all_nodes);
-    let id_display = display_node_id(&all_nodes);
+fn process_file(contents : &str, file_directory : &str){
+    let document = match parse_str(contents, &ParseOptions::default()) {
+        Ok(document) => document,
+        Err(error) => {
+            println!("File {file_directory} is not valid XML: {error}");
+            return;
+        }
+    };
+    let id_display = display_node_id(&document);
 
-    let run = false;
     let mut user_input = String::new();
-    display_main_menu(&file_directory);
+    display_main_menu(file_directory);
     println!("{id_display}");
-    while !run {
+    loop {
         user_input.clear();
         let _s = stdout().flush();
-        stdin().read_line(&mut user_input).expect("Did not enter a correct string");
+        let bytes_read = stdin().read_line(&mut user_input).expect("Did not enter a correct string");
+        if bytes_read == 0 { // End of input
+            break;
+        }
         user_input = clean_user_input(&mut user_input);
 
-        match user_input.trim().parse::<usize>() {
-            Ok(id) =>{ // This is a number
-                process_id(id, &tree_node);
-            },
-            Err(_) => ()
+        if let Ok(id) = user_input.trim().parse::<usize>() { // This is a number
+            process_id(NodeId::new(id), &document);
+            continue;
         }
 
         match user_input.to_lowercase().as_str() {
             "id" => println!("{id_display}"),
-            "menu" => display_main_menu(&file_directory),
+            "menu" => display_main_menu(file_directory),
+            "prolog" => display_prolog(&document),
+            "comments" => display_comments(&document),
             _ => println!("Error no such response for input :: {user_input}")
         }
 
     }
 }
 
-fn display_main_menu(file_directory : &String){
+fn display_main_menu(file_directory : &str){
     println!("\n
         Welcome, the file {file_directory} has been read!\n
         To investigate into the XML file you can select the ID of the node by typing it's number\n
@@ -66,6 +70,47 @@ fn display_main_menu(file_directory : &String){
     ")
 }
 
+fn display_prolog(document: &Document){
+    match document.get_declaration() {
+        Some(declaration) => {
+            println!("{declaration}");
+            println!("Version :: {}", declaration.get_version());
+            println!("Encoding :: {}", declaration.get_encoding().map(|encoding| encoding.as_str()).unwrap_or("not declared"));
+            match declaration.get_standalone() {
+                Some(standalone) => println!("Standalone :: {}\n", if standalone { "yes" } else { "no" }),
+                None => println!("Standalone :: not declared\n"),
+            }
+        }
+        None => println!("The file has no XML declaration\n"),
+    }
+    if let Some(doctype) = document.get_doctype() {
+        println!("Doctype :: {}", doctype.get_root_name());
+        if let Some(public_id) = doctype.get_public_id() {
+            println!("Public ID :: {public_id}");
+        }
+        if let Some(system_id) = doctype.get_system_id() {
+            println!("System ID :: {system_id}");
+        }
+        for (name, value) in doctype.get_entities() {
+            println!("Entity :: &{name}; = {value}");
+        }
+        println!();
+    }
+}
+
+fn display_comments(document: &Document){
+    let comments = document.get_comments();
+    if comments.is_empty() {
+        println!("The file has no comments\n");
+    }
+    for comment in comments {
+        match comment.get_parent() {
+            Some(parent_id) => println!("[ ID::{} || Parent ID::{} ] <!--{}-->", comment.get_id(), parent_id, comment.get_inner_element()),
+            None => println!("[ ID::{} || Top level ] <!--{}-->", comment.get_id(), comment.get_inner_element()),
+        }
+    }
+}
+
 fn clean_user_input(s : &mut String) -> String{
     if let Some('\n')=s.chars().next_back() {
         s.pop();
@@ -76,24 +121,32 @@ fn clean_user_input(s : &mut String) -> String{
     s.to_string()
 }
 
-fn display_node_id(all_node : &Vec<Node>) -> String{
+fn display_node_id(document: &Document) -> String{
     let mut id_display = String::new();
-    for node in all_node{
+    // Text nodes are shown as the inner element of their parent
+    for node in document.get_nodes().iter().filter(|node| !node.is_text()){
         id_display.push_str(&format!("[ ID::{}  || Node Name::{}]\n", node.get_id(), node.get_name()));
     }
     id_display
 }
 
-fn process_id(id: usize, tree: &HashMap<usize, &Node>) {
-    let current_node: Option<&&Node> = tree.get(&id);
-
-    match current_node {
+fn process_id(id: NodeId, document: &Document) {
+    match document.get(id) {
         Some(node) => {
             // Process the node
             println!("Node Name :: {} || Node ID :: {}", node.get_name(), node.get_id());
+            if let Some(span) = node.get_span() {
+                println!("Location :: {} || Bytes :: {:?}", span, span.byte_range);
+            }
+            if let Some(namespace) = node.get_namespace_uri() {
+                println!("Namespace :: {namespace} || Local Name :: {}", node.get_local_name());
+            }
             println!("Attributes :: {:?}", node.get_all_attributes());
-            println!("Parent ID :: {:?} || Children ID {:?}", node.get_parent(), node.get_child());
-            println!("Element :: {}\n", node.get_inner_element());
+            let parent = node.get_parent().map(|parent| parent.to_string()).unwrap_or_else(|| "None".to_string());
+            let children: Vec<String> = node.get_child().iter().map(NodeId::to_string).collect();
+            println!("Parent ID :: {} || Children ID [{}]", parent, children.join(", "));
+            let cdata = if node.is_cdata() { " (CDATA)" } else { "" };
+            println!("Element{} :: {}\n", cdata, node.get_inner_element());
         }
         None => {
             // Handle invalid ID
@@ -0,0 +1,496 @@
+use std::iter;
+use crate::attributes::{is_space_preserved, Attributes};
+use crate::document::Document;
+use crate::error::XmlError;
+use crate::namespaces::{resolve_namespaces, split_qname, NamespaceScope};
+use crate::tokenizer::{is_name_char, is_name_start_char, is_xml_char, Position};
+use crate::tree_struct::{Node, NodeId, NodeKind};
+use crate::xml_proc::join_text_children;
+
+// Changes to the tree of a Document. Every operation keeps the parent and child links of all nodes
+// in agreement and fails with XmlError::InvalidOperation, leaving the document as it was, when it
+// would make a node its own ancestor, put text outside the root or add a second root element.
+// What the parser derives from the surroundings of a node is brought up to date as well: the inner
+// element of the parents involved, and the namespaces and xml:space of the elements that moved.
+impl Document {
+    // New nodes are detached, attach them with append_child, insert_before or insert_after
+    pub fn create_element(&mut self, name: &str) -> Result<NodeId, XmlError> {
+        check_name(name)?;
+        Ok(self.add_node(|id| Node::new(name.to_string(), false, false, Attributes::new(), None, None, id)))
+    }
+
+    pub fn create_text(&mut self, text: &str) -> NodeId {
+        self.add_node(|id| Node::new_text(text.to_string(), false, id))
+    }
+
+    pub fn create_comment(&mut self, comment: &str) -> NodeId {
+        self.add_node(|id| Node::new_comment(comment.to_string(), id))
+    }
+
+    // Makes `child` the last child of `parent`, a child that is already in the tree is moved
+    pub fn append_child(&mut self, parent: NodeId, child: NodeId) -> Result<(), XmlError> {
+        self.check_insert(Some(parent), child)?;
+        self.unlink(child);
+        let index = self.node(parent)?.get_child().len();
+        self.link(Some(parent), index, child);
+        Ok(())
+    }
+
+    pub fn insert_before(&mut self, reference: NodeId, node: NodeId) -> Result<(), XmlError> {
+        self.insert_next_to(reference, node, 0)
+    }
+
+    pub fn insert_after(&mut self, reference: NodeId, node: NodeId) -> Result<(), XmlError> {
+        self.insert_next_to(reference, node, 1)
+    }
+
+    // Takes the node and its subtree out of the tree, it can be inserted again later.
+    // Its namespaces are then only the ones declared inside the subtree.
+    pub fn detach(&mut self, id: NodeId) -> Result<(), XmlError> {
+        self.node(id)?;
+        self.unlink(id);
+        self.refresh_subtree(id);
+        Ok(())
+    }
+
+    // Deletes the node and its subtree, their ids are not handed out again
+    pub fn remove(&mut self, id: NodeId) -> Result<(), XmlError> {
+        self.node(id)?;
+        self.unlink(id);
+        let subtree: Vec<NodeId> = self.descendants(id).chain(iter::once(id)).collect();
+        for removed in subtree {
+            self.nodes[removed.get_index()] = None;
+        }
+        Ok(())
+    }
+
+    // Puts `new` where `old` is, `old` is detached and can still be inserted elsewhere
+    pub fn replace(&mut self, old: NodeId, new: NodeId) -> Result<(), XmlError> {
+        if old == new {
+            return self.node(old).map(|_| ());
+        }
+        let (parent, _) = self.place(old)?;
+        // The old node leaves the tree, so only a cycle through its ancestors counts
+        if self.ancestors(old).any(|ancestor| ancestor == new) {
+            return Err(cycle(new));
+        }
+        self.check_insert_except(parent, new, Some(old))?;
+        self.unlink(new);
+        let (parent, index) = self.place(old)?;
+        self.unlink(old);
+        self.refresh_subtree(old);
+        self.link(parent, index, new);
+        Ok(())
+    }
+
+    // Returns the previous value, the namespace of a prefixed name is looked up on the element.
+    // xmlns and xml:space attributes apply to the whole subtree right away.
+    pub fn set_attribute(&mut self, id: NodeId, name: &str, value: &str) -> Result<Option<String>, XmlError> {
+        check_name(name)?;
+        check_value(value)?;
+        let node = self.element_mut(id)?;
+        let namespace = match split_qname(name) {
+            (Some(prefix), _) => node.get_namespaces().lookup(Some(prefix)).map(String::from),
+            (None, _) => None,
+        };
+        let attributes = node.get_all_attributes_mut();
+        let previous = attributes.insert(name.to_string(), value.to_string());
+        if let Some(attribute) = attributes.iter_mut().find(|attribute| attribute.get_name() == name) {
+            attribute.set_namespace_uri(namespace);
+        }
+        if changes_scope(name) {
+            self.refresh_subtree(id);
+        }
+        Ok(previous)
+    }
+
+    pub fn remove_attribute(&mut self, id: NodeId, name: &str) -> Result<Option<String>, XmlError> {
+        let removed = self.element_mut(id)?.get_all_attributes_mut().remove(name);
+        if removed.is_some() && changes_scope(name) {
+            self.refresh_subtree(id);
+        }
+        Ok(removed.map(|attribute| attribute.get_value().clone()))
+    }
+
+    fn add_node(&mut self, create: impl FnOnce(NodeId) -> Node) -> NodeId {
+        let id = self.ids.allocate();
+        self.nodes.push(Some(create(id)));
+        id
+    }
+
+    fn node(&self, id: NodeId) -> Result<&Node, XmlError> {
+        self.get(id).ok_or_else(|| invalid(format!("node {id} does not exist")))
+    }
+
+    fn element_mut(&mut self, id: NodeId) -> Result<&mut Node, XmlError> {
+        match self.get_mut(id) {
+            Some(node) if node.is_element() => Ok(node),
+            Some(_) => Err(invalid(format!("node {id} is not an element"))),
+            None => Err(invalid(format!("node {id} does not exist"))),
+        }
+    }
+
+    // The parent of an attached node and its index among the children, or among the top-level nodes
+    fn place(&self, id: NodeId) -> Result<(Option<NodeId>, usize), XmlError> {
+        let siblings = match self.node(id)?.get_parent() {
+            Some(parent) => (Some(parent), self.node(parent)?.get_child().as_slice()),
+            None => (None, self.top_level.as_slice()),
+        };
+        match siblings.1.iter().position(|&sibling| sibling == id) {
+            Some(index) => Ok((siblings.0, index)),
+            None => Err(invalid(format!("node {id} is not in the tree"))),
+        }
+    }
+
+    fn insert_next_to(&mut self, reference: NodeId, node: NodeId, offset: usize) -> Result<(), XmlError> {
+        if reference == node {
+            return Err(invalid(format!("node {node} cannot be inserted next to itself")));
+        }
+        let (parent, _) = self.place(reference)?;
+        self.check_insert(parent, node)?;
+        self.unlink(node);
+        let (parent, index) = self.place(reference)?;
+        self.link(parent, index + offset, node);
+        Ok(())
+    }
+
+    fn check_insert(&self, parent: Option<NodeId>, node: NodeId) -> Result<(), XmlError> {
+        self.check_insert_except(parent, node, None)
+    }
+
+    // `leaving` is a node that is taken out of the tree by the same operation
+    fn check_insert_except(&self, parent: Option<NodeId>, node: NodeId, leaving: Option<NodeId>) -> Result<(), XmlError> {
+        let inserted = self.node(node)?;
+        match parent {
+            Some(parent) => {
+                if !self.node(parent)?.is_element() {
+                    return Err(invalid(format!("node {parent} is not an element and cannot have children")));
+                }
+                if parent == node || self.ancestors(parent).any(|ancestor| ancestor == node) {
+                    return Err(cycle(node));
+                }
+            }
+            None if inserted.is_text() => return Err(invalid("text is not allowed outside the root element".to_string())),
+            None if inserted.is_element() => {
+                let other_root = self.top_level.iter()
+                    .any(|&id| id != node && Some(id) != leaving && self.get(id).is_some_and(Node::is_element));
+                if other_root {
+                    return Err(invalid("a document can only have one root element".to_string()));
+                }
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    // Takes the node out of the child list of its parent, or out of the top-level nodes
+    fn unlink(&mut self, id: NodeId) {
+        let parent = self.get(id).and_then(Node::get_parent);
+        match parent.and_then(|parent| self.get_mut(parent)) {
+            Some(parent) => {
+                parent.remove_child(id);
+            }
+            None => self.top_level.retain(|&top| top != id),
+        }
+        if let Some(node) = self.get_mut(id) {
+            node.remove_parent();
+            node.set_root(false);
+        }
+        if let Some(parent) = parent {
+            self.refresh_inner_element(parent);
+        }
+    }
+
+    fn link(&mut self, parent: Option<NodeId>, index: usize, id: NodeId) {
+        let depth = match parent.and_then(|parent| self.get_mut(parent)) {
+            Some(parent_node) => {
+                parent_node.insert_child(index, id);
+                parent_node.get_depth() + 1
+            }
+            None => {
+                self.top_level.insert(index.min(self.top_level.len()), id);
+                0
+            }
+        };
+        if let Some(node) = self.get_mut(id) {
+            match parent {
+                Some(parent) => node.set_parent(parent),
+                None => node.set_root(node.is_element()),
+            }
+        }
+        self.set_depths(id, depth);
+        self.refresh_subtree(id);
+        if let Some(parent) = parent {
+            self.refresh_inner_element(parent);
+        }
+    }
+
+    fn set_depths(&mut self, id: NodeId, depth: usize) {
+        let mut stack = vec![(id, depth)];
+        while let Some((next, depth)) = stack.pop() {
+            if let Some(node) = self.get_mut(next) {
+                node.set_depth(depth);
+                stack.extend(node.get_child().iter().map(|&child| (child, depth + 1)));
+            }
+        }
+    }
+
+    // Resolves the namespaces and xml:space of every element in the subtree again from its new ancestors,
+    // parents before their children
+    fn refresh_subtree(&mut self, id: NodeId) {
+        let subtree: Vec<NodeId> = iter::once(id).chain(self.descendants(id).elements()).collect();
+        for &element in &subtree {
+            let (scope, inherited_space) = match self.parent(element).and_then(|parent| self.get(parent)) {
+                Some(parent) => (parent.get_namespaces().clone(), parent.is_space_preserved()),
+                None => (NamespaceScope::new(), false),
+            };
+            let Some(node) = self.get_mut(element).filter(|node| node.is_element()) else {
+                continue;
+            };
+            let name = node.get_name().clone();
+            // Without strict checks resolving cannot fail, unknown prefixes are in no namespace
+            if let Ok((namespace, namespaces)) = resolve_namespaces(&name, node.get_all_attributes_mut(), &scope, false, Position::default()) {
+                node.set_namespace_uri(namespace);
+                node.set_namespaces(namespaces);
+            }
+            let space_preserved = is_space_preserved(node.get_all_attributes(), inherited_space);
+            node.set_space_preserved(space_preserved);
+        }
+    }
+
+    // The inner element of an element is the text of its text children, see collect_inner_elements
+    fn refresh_inner_element(&mut self, id: NodeId) {
+        if !self.get(id).is_some_and(Node::is_element) {
+            return;
+        }
+        let texts: Vec<&Node> = self.children(id).of_kind(NodeKind::Text).filter_map(|text| self.get(text)).collect();
+        let (inner_element, raw_inner_element, cdata) = join_text_children(&texts);
+        if let Some(node) = self.get_mut(id) {
+            node.set_inner_element(inner_element);
+            node.set_cdata(cdata);
+            match raw_inner_element {
+                Some(raw_inner_element) => node.set_raw_inner_element(raw_inner_element),
+                None => node.remove_raw_inner_element(),
+            }
+        }
+    }
+}
+
+fn check_name(name: &str) -> Result<(), XmlError> {
+    let mut chars = name.chars();
+    let legal = chars.next().is_some_and(is_name_start_char) && chars.all(is_name_char);
+    if legal {
+        Ok(())
+    } else {
+        Err(invalid(format!("{name:?} is not a valid name")))
+    }
+}
+
+// Attribute values may only hold the characters of the Char production, like parsed ones
+fn check_value(value: &str) -> Result<(), XmlError> {
+    match value.chars().find(|&c| !is_xml_char(c)) {
+        Some(character) => Err(invalid(format!("{character:?} is not allowed in an attribute value"))),
+        None => Ok(()),
+    }
+}
+
+// Namespace declarations and xml:space are inherited, changing them affects the whole subtree
+fn changes_scope(name: &str) -> bool {
+    name == "xmlns" || name.starts_with("xmlns:") || name == "xml:space"
+}
+
+fn invalid(message: String) -> XmlError {
+    XmlError::InvalidOperation { message }
+}
+
+fn cycle(node: NodeId) -> XmlError {
+    invalid(format!("node {node} would become its own ancestor"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::options::ParseOptions;
+    use crate::xml_proc::parse_str;
+    use super::*;
+
+    // config 0, server 1, host 2, "a" 3, port 4, "80" 5, logging 6
+    const CONFIG: &str = "<config><server><host>a</host><port>80</port></server><logging/></config>";
+
+    fn names(document: &Document, id: NodeId) -> Vec<String> {
+        document.children(id).map(|child| document.get(child).unwrap().get_name().clone()).collect()
+    }
+
+    fn parse() -> Document {
+        parse_str(CONFIG, &ParseOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn test_create_and_insert() {
+        let mut document = parse();
+        let server = NodeId::new(1);
+        let timeout = document.create_element("timeout").unwrap();
+        let value = document.create_text("30");
+        document.append_child(timeout, value).unwrap();
+        document.insert_after(NodeId::new(2), timeout).unwrap();
+        assert_eq!(names(&document, server), vec!["host", "timeout", "port"]);
+        assert_eq!(document.parent(timeout), Some(server));
+        assert_eq!(document.get(timeout).unwrap().get_depth(), 2);
+        assert_eq!(document.get(value).unwrap().get_depth(), 3);
+        assert_eq!(document.text_content(server), "a3080");
+
+        let comment = document.create_comment(" generated ");
+        document.insert_before(document.root().unwrap(), comment).unwrap();
+        assert_eq!(document.get_top_level_nodes(), vec![comment, NodeId::new(0)]);
+        assert!(document.create_element("1st").is_err());
+    }
+
+    #[test]
+    fn test_move_detach_and_remove() {
+        let mut document = parse();
+        let (server, port, logging) = (NodeId::new(1), NodeId::new(4), NodeId::new(6));
+        document.append_child(logging, port).unwrap();
+        assert_eq!(names(&document, server), vec!["host"]);
+        assert_eq!(document.get(logging).unwrap().get_child(), &vec![port]);
+        assert_eq!(document.get(NodeId::new(5)).unwrap().get_depth(), 3);
+
+        document.detach(logging).unwrap();
+        assert_eq!(names(&document, NodeId::new(0)), vec!["server"]);
+        assert_eq!(document.parent(logging), None);
+        assert!(!document.get_top_level_nodes().contains(&logging));
+
+        document.remove(server).unwrap();
+        assert!(document.get(server).is_none());
+        assert!(document.get(NodeId::new(3)).is_none());
+        assert_eq!(document.get_nodes().len(), 4);
+        assert_eq!(document.create_text("new"), NodeId::new(7));
+        assert!(document.remove(server).is_err());
+    }
+
+    #[test]
+    fn test_cycles_are_rejected() {
+        let mut document = parse();
+        let (config, server, host) = (NodeId::new(0), NodeId::new(1), NodeId::new(2));
+        assert!(matches!(document.append_child(host, server), Err(XmlError::InvalidOperation { .. })));
+        assert!(document.append_child(server, server).is_err());
+        assert!(document.insert_before(host, config).is_err());
+        assert!(document.append_child(NodeId::new(3), host).is_err());
+        assert!(document.replace(host, server).is_err());
+
+        // Nothing changed
+        assert_eq!(names(&document, config), vec!["server", "logging"]);
+        assert_eq!(names(&document, server), vec!["host", "port"]);
+        assert_eq!(document.parent(server), Some(config));
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut document = parse();
+        let (config, server) = (NodeId::new(0), NodeId::new(1));
+        let backend = document.create_element("backend").unwrap();
+        document.replace(server, backend).unwrap();
+        assert_eq!(names(&document, config), vec!["backend", "logging"]);
+        assert_eq!(document.parent(server), None);
+
+        // The root can be replaced, but there is only ever one root element
+        let root = document.create_element("settings").unwrap();
+        assert!(document.insert_after(config, root).is_err());
+        document.replace(config, root).unwrap();
+        assert_eq!(document.root(), Some(root));
+        assert!(document.get(root).unwrap().is_root());
+        assert!(!document.get(config).unwrap().is_root());
+        let text = document.create_text("x");
+        assert!(document.insert_before(root, text).is_err());
+    }
+
+    #[test]
+    fn test_attributes() {
+        let mut document = parse_str("<a xmlns:n=\"urn:n\" x=\"1\">t</a>", &ParseOptions::default()).unwrap();
+        let a = document.root().unwrap();
+        assert_eq!(document.set_attribute(a, "x", "2").unwrap(), Some("1".to_string()));
+        assert_eq!(document.set_attribute(a, "n:y", "3").unwrap(), None);
+        let attributes = document.get(a).unwrap().get_all_attributes();
+        assert_eq!(attributes.get("x").unwrap(), "2");
+        assert_eq!(attributes.get_ns(Some("urn:n"), "y").unwrap().get_value(), "3");
+
+        assert_eq!(document.remove_attribute(a, "x").unwrap(), Some("2".to_string()));
+        assert_eq!(document.remove_attribute(a, "x").unwrap(), None);
+        assert!(document.set_attribute(NodeId::new(1), "x", "1").is_err());
+        assert!(document.set_attribute(a, "a b", "1").is_err());
+        assert!(matches!(document.set_attribute(a, "x", "\u{1}"), Err(XmlError::InvalidOperation { .. })));
+        assert!(document.get(a).unwrap().get_all_attributes().get("x").is_none());
+    }
+
+    #[test]
+    fn test_inner_element_follows_text_children() {
+        let mut document = parse_str("<a>old</a>", &ParseOptions::default()).unwrap();
+        let a = document.root().unwrap();
+        let old = document.children(a).next().unwrap();
+        let new = document.create_text("new");
+        document.replace(old, new).unwrap();
+        assert_eq!(document.get(a).unwrap().get_inner_element(), "new");
+        assert_eq!(document.get(a).unwrap().get_inner_element(), &document.text_content(a));
+
+        let more = document.create_text(" text");
+        document.append_child(a, more).unwrap();
+        assert_eq!(document.get(a).unwrap().get_inner_element(), "new text");
+        document.detach(new).unwrap();
+        assert_eq!(document.get(a).unwrap().get_inner_element(), " text");
+        document.remove(more).unwrap();
+        assert_eq!(document.get(a).unwrap().get_inner_element(), "");
+        assert_eq!(document.get(a).unwrap().get_raw_inner_element(), None);
+
+        let mut document = parse_str("<a><![CDATA[x]]></a>", &ParseOptions::default()).unwrap();
+        let a = document.root().unwrap();
+        assert!(document.get(a).unwrap().is_cdata());
+        let cdata = document.children(a).next().unwrap();
+        document.remove(cdata).unwrap();
+        assert!(!document.get(a).unwrap().is_cdata());
+    }
+
+    #[test]
+    fn test_moved_nodes_take_namespaces_and_space_from_new_parent() {
+        let source = "<r><x xmlns:p=\"urn:p\" xml:space=\"preserve\"><p:y p:k=\"1\"> t </p:y></x><z/></r>";
+        let mut document = parse_str(source, &ParseOptions::default()).unwrap();
+        // r 0, x 1, p:y 2, " t " 3, z 4
+        let (x, y, z) = (NodeId::new(1), NodeId::new(2), NodeId::new(4));
+        let node = document.get(y).unwrap();
+        assert_eq!(node.get_namespace_uri().map(String::as_str), Some("urn:p"));
+        assert!(node.is_space_preserved());
+        assert_eq!(node.get_inner_element(), " t ");
+
+        document.append_child(z, y).unwrap();
+        let node = document.get(y).unwrap();
+        assert_eq!(node.get_namespace_uri(), None);
+        assert_eq!(node.get_namespaces().lookup(Some("p")), None);
+        assert_eq!(node.get_all_attributes().iter().next().unwrap().get_namespace_uri(), None);
+        assert!(!node.is_space_preserved());
+
+        document.append_child(x, y).unwrap();
+        let node = document.get(y).unwrap();
+        assert_eq!(node.get_namespace_uri().map(String::as_str), Some("urn:p"));
+        assert!(node.is_space_preserved());
+
+        document.detach(y).unwrap();
+        assert_eq!(document.get(y).unwrap().get_namespace_uri(), None);
+    }
+
+    #[test]
+    fn test_namespace_attributes_update_the_scope() {
+        let mut document = parse_str("<r><p:a><p:b/></p:a></r>", &ParseOptions::default()).unwrap();
+        let (r, a, b) = (NodeId::new(0), NodeId::new(1), NodeId::new(2));
+        assert_eq!(document.get(b).unwrap().get_namespace_uri(), None);
+
+        document.set_attribute(r, "xmlns:p", "urn:p").unwrap();
+        assert_eq!(document.get(r).unwrap().get_namespaces().lookup(Some("p")), Some("urn:p"));
+        assert_eq!(document.get(a).unwrap().get_namespace_uri().map(String::as_str), Some("urn:p"));
+        assert_eq!(document.get(b).unwrap().get_namespace_uri().map(String::as_str), Some("urn:p"));
+
+        document.set_attribute(a, "xml:space", "preserve").unwrap();
+        assert!(document.get(b).unwrap().is_space_preserved());
+
+        document.remove_attribute(r, "xmlns:p").unwrap();
+        assert_eq!(document.get(r).unwrap().get_namespaces().lookup(Some("p")), None);
+        assert_eq!(document.get(b).unwrap().get_namespace_uri(), None);
+    }
+}
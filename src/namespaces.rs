@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use crate::attributes::Attributes;
+use crate::error::XmlError;
+use crate::tokenizer::Position;
+
+// The prefixes "xml" and "xmlns" are bound to these without being declared
+pub const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+pub const XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
+
+// Splits "prefix:local" at the first colon, a name without a colon has no prefix
+pub fn split_qname(name: &str) -> (Option<&str>, &str) {
+    match name.split_once(':') {
+        Some((prefix, local_name)) => (Some(prefix), local_name),
+        None => (None, name),
+    }
+}
+
+// The namespace bindings in scope on an element, the default namespace uses "" as its prefix.
+// Elements that declare nothing share the map of their parent.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NamespaceScope {
+    bindings: Arc<HashMap<String, String>>,
+}
+
+impl NamespaceScope {
+    pub fn new() -> NamespaceScope {
+        NamespaceScope::default()
+    }
+
+    // The namespace URI bound to `prefix`, None looks up the default namespace
+    pub fn lookup(&self, prefix: Option<&str>) -> Option<&str> {
+        match prefix {
+            Some("xml") => Some(XML_NAMESPACE),
+            Some("xmlns") => Some(XMLNS_NAMESPACE),
+            Some(prefix) => self.bindings.get(prefix).map(String::as_str),
+            None => self.bindings.get("").map(String::as_str),
+        }
+    }
+
+    pub fn get_bindings(&self) -> &HashMap<String, String> {
+        &self.bindings
+    }
+
+    // The scope of a child element with the given (prefix, uri) declarations, an empty uri
+    // undeclares the default namespace
+    pub fn declare(&self, declarations: Vec<(String, String)>) -> NamespaceScope {
+        if declarations.is_empty() {
+            return self.clone();
+        }
+        let mut bindings = HashMap::clone(&self.bindings);
+        for (prefix, uri) in declarations {
+            if uri.is_empty() {
+                bindings.remove(&prefix);
+            } else {
+                bindings.insert(prefix, uri);
+            }
+        }
+        NamespaceScope { bindings: Arc::new(bindings) }
+    }
+}
+
+// Reads the xmlns declarations of an element, resolves the prefixes of the element and its attributes
+// and returns the namespace of the element together with the scope for its content
+pub fn resolve_namespaces(name: &str, attributes: &mut Attributes, parent: &NamespaceScope, strict: bool, position: Position) -> Result<(Option<String>, NamespaceScope), XmlError> {
+    let mut declarations: Vec<(String, String)> = Vec::new();
+    for attribute in attributes.iter() {
+        let prefix = match split_qname(attribute.get_name()) {
+            (None, "xmlns") => String::new(),
+            (Some("xmlns"), prefix) => prefix.to_string(),
+            _ => continue,
+        };
+        let uri = attribute.get_value();
+        if strict {
+            check_declaration(&prefix, uri, position)?;
+        }
+        declarations.push((prefix, uri.clone()));
+    }
+    let scope = parent.declare(declarations);
+
+    let namespace = resolve(name, true, &scope, strict, position)?;
+    let mut expanded_names: Vec<(Option<String>, String)> = Vec::new();
+    for attribute in attributes.iter_mut() {
+        let attribute_namespace = resolve(attribute.get_name(), false, &scope, strict, position)?;
+        let expanded_name = (attribute_namespace.clone(), attribute.get_local_name().to_string());
+        if strict && attribute_namespace.is_some() && expanded_names.contains(&expanded_name) {
+            return Err(XmlError::InvalidAttribute {
+                message: format!("{} has the same namespace and local name as another attribute", attribute.get_name()),
+                position,
+            });
+        }
+        expanded_names.push(expanded_name);
+        attribute.set_namespace_uri(attribute_namespace);
+    }
+    Ok((namespace, scope))
+}
+
+// Unprefixed attributes are in no namespace, unprefixed elements are in the default namespace
+fn resolve(name: &str, element: bool, scope: &NamespaceScope, strict: bool, position: Position) -> Result<Option<String>, XmlError> {
+    let (prefix, local_name) = split_qname(name);
+    if strict && (prefix == Some("") || local_name.is_empty() || local_name.contains(':')) {
+        return Err(XmlError::InvalidSyntax { message: format!("{name} is not a valid qualified name"), position });
+    }
+    match prefix {
+        None if !element && name == "xmlns" => Ok(Some(XMLNS_NAMESPACE.to_string())),
+        None if !element => Ok(None),
+        _ => match scope.lookup(prefix) {
+            Some(uri) => Ok(Some(uri.to_string())),
+            None if strict && prefix.is_some() => Err(XmlError::UndeclaredPrefix { prefix: prefix.unwrap_or_default().to_string(), position }),
+            None => Ok(None),
+        },
+    }
+}
+
+fn check_declaration(prefix: &str, uri: &str, position: Position) -> Result<(), XmlError> {
+    let error = |message: String| Err(XmlError::InvalidAttribute { message, position });
+    match prefix {
+        "xmlns" => error("the xmlns prefix cannot be declared".to_string()),
+        "xml" if uri != XML_NAMESPACE => error(format!("the xml prefix can only be bound to {XML_NAMESPACE}")),
+        _ if prefix != "xml" && (uri == XML_NAMESPACE || uri == XMLNS_NAMESPACE) => error(format!("{uri} cannot be bound to another prefix")),
+        _ if !prefix.is_empty() && uri.is_empty() => error(format!("the {prefix} prefix cannot be undeclared")),
+        _ => Ok(()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attributes(pairs: &[(&str, &str)]) -> Attributes {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_split_qname() {
+        assert_eq!(split_qname("soap:Envelope"), (Some("soap"), "Envelope"));
+        assert_eq!(split_qname("Envelope"), (None, "Envelope"));
+    }
+
+    #[test]
+    fn test_declarations_and_resolution() {
+        let mut list = attributes(&[("xmlns", "urn:default"), ("xmlns:s", "urn:soap"), ("s:mustUnderstand", "1"), ("id", "a")]);
+        let (namespace, scope) = resolve_namespaces("s:Envelope", &mut list, &NamespaceScope::new(), true, Position::default()).unwrap();
+        assert_eq!(namespace.as_deref(), Some("urn:soap"));
+        assert_eq!(scope.lookup(None), Some("urn:default"));
+        assert_eq!(scope.lookup(Some("xml")), Some(XML_NAMESPACE));
+        assert_eq!(list.get_attribute("s:mustUnderstand").unwrap().get_namespace_uri().unwrap(), "urn:soap");
+        assert_eq!(list.get_attribute("id").unwrap().get_namespace_uri(), None);
+        assert_eq!(list.get_attribute("xmlns:s").unwrap().get_namespace_uri().unwrap(), XMLNS_NAMESPACE);
+
+        // Undeclaring the default namespace in a child
+        let mut child = attributes(&[("xmlns", "")]);
+        let (namespace, child_scope) = resolve_namespaces("Body", &mut child, &scope, true, Position::default()).unwrap();
+        assert_eq!(namespace, None);
+        assert_eq!(child_scope.lookup(Some("s")), Some("urn:soap"));
+    }
+
+    #[test]
+    fn test_scope_is_shared_without_declarations() {
+        let scope = NamespaceScope::new().declare(vec![("a".to_string(), "urn:a".to_string())]);
+        let child = scope.declare(Vec::new());
+        assert!(Arc::ptr_eq(&scope.bindings, &child.bindings));
+    }
+
+    // Parsed documents are handed from one thread to another, everything that holds a scope must be Send
+    #[test]
+    fn test_scope_can_cross_threads() {
+        fn assert_send<T: Send + Sync>() {}
+        assert_send::<NamespaceScope>();
+        assert_send::<crate::tree_struct::Node>();
+        assert_send::<crate::document::Document>();
+        assert_send::<crate::reader::XmlEvent>();
+    }
+
+    #[test]
+    fn test_undeclared_prefix() {
+        let error = resolve_namespaces("x:a", &mut Attributes::new(), &NamespaceScope::new(), true, Position::default()).unwrap_err();
+        assert_eq!(error, XmlError::UndeclaredPrefix { prefix: "x".to_string(), position: Position::default() });
+        let (namespace, _) = resolve_namespaces("x:a", &mut Attributes::new(), &NamespaceScope::new(), false, Position::default()).unwrap();
+        assert_eq!(namespace, None);
+    }
+
+    #[test]
+    fn test_invalid_declarations() {
+        let resolve_strict = |pairs: &[(&str, &str)]| resolve_namespaces("a", &mut attributes(pairs), &NamespaceScope::new(), true, Position::default());
+        assert!(resolve_strict(&[("xmlns:xml", "urn:other")]).is_err());
+        assert!(resolve_strict(&[("xmlns:xml", XML_NAMESPACE)]).is_ok());
+        assert!(resolve_strict(&[("xmlns:xmlns", XMLNS_NAMESPACE)]).is_err());
+        assert!(resolve_strict(&[("xmlns:p", "")]).is_err());
+        assert!(resolve_strict(&[("xmlns:a", "urn:x"), ("xmlns:b", "urn:x"), ("a:id", "1"), ("b:id", "2")]).is_err());
+    }
+}
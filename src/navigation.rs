@@ -0,0 +1,191 @@
+use std::iter;
+use crate::document::Document;
+use crate::tree_struct::{NodeId, NodeKind};
+
+// Iterator over node handles of a document, returned by the navigation methods of Document.
+// It can be narrowed down to nodes of one kind or with one name.
+pub struct Nodes<'a, I> {
+    document: &'a Document,
+    ids: I,
+}
+
+impl<'a, I: Iterator<Item = NodeId> + 'a> Nodes<'a, I> {
+    pub fn new(document: &'a Document, ids: I) -> Nodes<'a, I> {
+        Nodes { document, ids }
+    }
+
+    pub fn of_kind(self, kind: NodeKind) -> Nodes<'a, impl Iterator<Item = NodeId> + 'a> {
+        let document = self.document;
+        let ids = self.ids.filter(move |&id| document.get(id).is_some_and(|node| node.get_kind() == kind));
+        Nodes { document, ids }
+    }
+
+    pub fn elements(self) -> Nodes<'a, impl Iterator<Item = NodeId> + 'a> {
+        self.of_kind(NodeKind::Element)
+    }
+
+    // Matches the name as written, prefix included
+    pub fn named(self, name: &'a str) -> Nodes<'a, impl Iterator<Item = NodeId> + 'a> {
+        let document = self.document;
+        let ids = self.ids.filter(move |&id| document.get(id).is_some_and(|node| node.get_name() == name));
+        Nodes { document, ids }
+    }
+}
+
+impl<I: Iterator<Item = NodeId>> Iterator for Nodes<'_, I> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        self.ids.next()
+    }
+}
+
+// Navigation follows the parent and child links, so it stays correct after the tree is changed.
+// Nodes without a parent (the root and the comments and processing instructions around it) are siblings.
+impl Document {
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.get(id)?.get_parent()
+    }
+
+    pub fn children(&self, id: NodeId) -> Nodes<'_, impl Iterator<Item = NodeId> + '_> {
+        let children = self.get(id).map(|node| node.get_child().as_slice()).unwrap_or_default();
+        Nodes::new(self, children.iter().copied())
+    }
+
+    pub fn first_child_element(&self, id: NodeId) -> Option<NodeId> {
+        self.children(id).elements().next()
+    }
+
+    // The parent of the node, its parent and so on up to the root
+    pub fn ancestors(&self, id: NodeId) -> Nodes<'_, impl Iterator<Item = NodeId> + '_> {
+        Nodes::new(self, iter::successors(self.parent(id), move |&ancestor| self.parent(ancestor)))
+    }
+
+    // Every node below `id` in document order, parents before their children
+    pub fn descendants(&self, id: NodeId) -> Nodes<'_, impl Iterator<Item = NodeId> + '_> {
+        let mut stack: Vec<NodeId> = self.children(id).collect::<Vec<NodeId>>().into_iter().rev().collect();
+        let ids = iter::from_fn(move || {
+            let next = stack.pop()?;
+            stack.extend(self.children(next).collect::<Vec<NodeId>>().into_iter().rev());
+            Some(next)
+        });
+        Nodes::new(self, ids)
+    }
+
+    // Every node below `id` with children before their parents
+    pub fn descendants_post_order(&self, id: NodeId) -> Nodes<'_, impl Iterator<Item = NodeId> + '_> {
+        // Each entry is a node and whether its children have been visited already
+        let mut stack: Vec<(NodeId, bool)> = self.children(id).map(|child| (child, false)).collect::<Vec<_>>().into_iter().rev().collect();
+        let ids = iter::from_fn(move || {
+            while let Some((next, visited)) = stack.pop() {
+                if visited {
+                    return Some(next);
+                }
+                stack.push((next, true));
+                stack.extend(self.children(next).map(|child| (child, false)).collect::<Vec<_>>().into_iter().rev());
+            }
+            None
+        });
+        Nodes::new(self, ids)
+    }
+
+    // The child list the node is in, borrowed from its parent or the top-level nodes
+    fn siblings(&self, id: NodeId) -> &[NodeId] {
+        match self.parent(id) {
+            Some(parent) => self.get(parent).map(|node| node.get_child().as_slice()).unwrap_or_default(),
+            None => &self.top_level,
+        }
+    }
+
+    // The siblings after the node, closest first
+    pub fn following_siblings(&self, id: NodeId) -> Nodes<'_, impl Iterator<Item = NodeId> + '_> {
+        let siblings = self.siblings(id);
+        let after = siblings.iter().position(|&sibling| sibling == id).map_or(siblings.len(), |index| index + 1);
+        Nodes::new(self, siblings[after..].iter().copied())
+    }
+
+    // The siblings before the node, closest first
+    pub fn preceding_siblings(&self, id: NodeId) -> Nodes<'_, impl Iterator<Item = NodeId> + '_> {
+        let siblings = self.siblings(id);
+        let before = siblings.iter().position(|&sibling| sibling == id).unwrap_or(0);
+        Nodes::new(self, siblings[..before].iter().rev().copied())
+    }
+
+    pub fn next_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.following_siblings(id).next()
+    }
+
+    pub fn previous_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.preceding_siblings(id).next()
+    }
+
+    // Every node that starts after the end of `id`, in document order
+    pub fn following(&self, id: NodeId) -> Nodes<'_, impl Iterator<Item = NodeId> + '_> {
+        let ids = iter::once(id).chain(self.ancestors(id))
+            .flat_map(move |node| self.following_siblings(node))
+            .flat_map(move |sibling| iter::once(sibling).chain(self.descendants(sibling)));
+        Nodes::new(self, ids)
+    }
+
+    // Every node that ends before the start of `id`, closest first, ancestors are not included
+    pub fn preceding(&self, id: NodeId) -> Nodes<'_, impl Iterator<Item = NodeId> + '_> {
+        let ids = iter::once(id).chain(self.ancestors(id))
+            .flat_map(move |node| self.preceding_siblings(node))
+            .flat_map(move |sibling| {
+                let subtree: Vec<NodeId> = iter::once(sibling).chain(self.descendants(sibling)).collect();
+                subtree.into_iter().rev()
+            });
+        Nodes::new(self, ids)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::options::ParseOptions;
+    use crate::xml_proc::parse_str;
+    use super::*;
+
+    // <!--c--> 0, r 1, a 2, x 3, "t" 4, b 5, y 6, z 7, <?p?> 8
+    const XML: &str = "<!--c--><r><a><x>t</x></a><b><y/><z/></b></r><?p?>";
+
+    fn names(document: &Document, ids: impl Iterator<Item = NodeId>) -> Vec<String> {
+        ids.map(|id| document.get(id).unwrap().get_name().clone()).collect()
+    }
+
+    #[test]
+    fn test_children_and_descendants() {
+        let document = parse_str(XML, &ParseOptions::default()).unwrap();
+        let r = document.root().unwrap();
+        assert_eq!(names(&document, document.children(r)), vec!["a", "b"]);
+        assert_eq!(names(&document, document.descendants(r)), vec!["a", "x", "#text", "b", "y", "z"]);
+        assert_eq!(names(&document, document.descendants_post_order(r)), vec!["#text", "x", "a", "y", "z", "b"]);
+        assert_eq!(names(&document, document.descendants(r).elements()), vec!["a", "x", "b", "y", "z"]);
+        assert_eq!(document.descendants(r).named("z").collect::<Vec<NodeId>>(), vec![NodeId::new(7)]);
+        assert_eq!(document.descendants(r).of_kind(NodeKind::Text).count(), 1);
+        assert_eq!(document.first_child_element(NodeId::new(5)), Some(NodeId::new(6)));
+        assert_eq!(document.first_child_element(NodeId::new(6)), None);
+    }
+
+    #[test]
+    fn test_ancestors_and_siblings() {
+        let document = parse_str(XML, &ParseOptions::default()).unwrap();
+        assert_eq!(names(&document, document.ancestors(NodeId::new(4))), vec!["x", "a", "r"]);
+        assert_eq!(document.next_sibling(NodeId::new(2)), Some(NodeId::new(5)));
+        assert_eq!(document.next_sibling(NodeId::new(5)), None);
+        assert_eq!(document.previous_sibling(NodeId::new(7)), Some(NodeId::new(6)));
+        assert_eq!(document.previous_sibling(NodeId::new(6)), None);
+
+        // Top-level nodes are siblings of the root
+        assert_eq!(names(&document, document.preceding_siblings(NodeId::new(8))), vec!["r", "#comment"]);
+        assert_eq!(document.next_sibling(NodeId::new(0)), Some(NodeId::new(1)));
+    }
+
+    #[test]
+    fn test_following_and_preceding() {
+        let document = parse_str(XML, &ParseOptions::default()).unwrap();
+        assert_eq!(names(&document, document.following(NodeId::new(3))), vec!["b", "y", "z", "p"]);
+        assert_eq!(names(&document, document.preceding(NodeId::new(6))), vec!["#text", "x", "a", "#comment"]);
+        assert_eq!(names(&document, document.preceding(NodeId::new(6)).elements()), vec!["x", "a"]);
+    }
+}
@@ -0,0 +1,75 @@
+// Settings that change how a document is parsed, the default is the lenient parser
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    // Enforce the XML 1.0 well-formedness rules and fail on the first violation
+    pub strict: bool,
+    // Keep text and attribute values as written next to their decoded form
+    pub retain_raw: bool,
+    pub whitespace: Whitespace,
+    pub limits: Limits,
+}
+
+impl ParseOptions {
+    pub fn strict() -> ParseOptions {
+        ParseOptions { strict: true, ..ParseOptions::default() }
+    }
+}
+
+// What happens to whitespace in text, inside an element with xml:space="preserve" it is always kept as written.
+// CDATA sections are never changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Whitespace {
+    // Every text node is kept as written, including the ones between tags that only hold whitespace
+    Preserve,
+    // Leading and trailing whitespace is removed, text nodes that end up empty are dropped
+    Trim,
+    // Like Trim, and every run of whitespace inside the text becomes a single space
+    Collapse,
+    // Text nodes that only hold whitespace are dropped, other text is kept as written.
+    // Earlier versions also trimmed the text of every element, Trim does that.
+    #[default]
+    IgnoreWhitespaceOnly,
+}
+
+// Upper bounds that protect against hostile input, going over one fails with XmlError::LimitExceeded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    // Total bytes of replacement text produced by declared entities in one document
+    pub max_entity_expansion_size: usize,
+    // How many declared entities may be expanded inside each other
+    pub max_entity_depth: usize,
+    // How many elements may be open at the same time
+    pub max_element_depth: usize,
+    pub max_attributes: usize,
+    pub max_name_length: usize,
+    // Size of the input in bytes once it is decoded to UTF-8, which is what the parser holds in memory.
+    // UTF-16 and Latin-1 input is counted by its UTF-8 length, not by the bytes that were read.
+    pub max_document_size: usize,
+}
+
+impl Limits {
+    // No limits at all, only for input that is trusted
+    pub fn unlimited() -> Limits {
+        Limits {
+            max_entity_expansion_size: usize::MAX,
+            max_entity_depth: usize::MAX,
+            max_element_depth: usize::MAX,
+            max_attributes: usize::MAX,
+            max_name_length: usize::MAX,
+            max_document_size: usize::MAX,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_entity_expansion_size: 1024 * 1024,
+            max_entity_depth: 16,
+            max_element_depth: 256,
+            max_attributes: 256,
+            max_name_length: 1024,
+            max_document_size: 256 * 1024 * 1024,
+        }
+    }
+}
@@ -0,0 +1,884 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead};
+use crate::attributes::{normalize_attribute_value, Attribute, Attributes};
+use crate::doctype::Doctype;
+use crate::document::XmlDeclaration;
+use crate::encoding::{detect_encoding, Decoder, Encoding};
+use crate::entities::EntityDecoder;
+use crate::error::{Limit, XmlError};
+use crate::namespaces::{resolve_namespaces, NamespaceScope};
+use crate::options::{ParseOptions, Whitespace};
+use crate::tokenizer::{find_doctype_end, is_name_char, is_name_start_char, is_whitespace, is_xml_char, Position, Tokenizer, TokenKind};
+
+// What the reader found in the document. Text and attribute values are decoded, names are checked
+// and every start element is followed by its end element, also for <empty/> elements.
+#[derive(Debug, Clone, PartialEq)]
+pub enum XmlEvent {
+    Declaration { declaration: XmlDeclaration, position: Position },
+    Doctype { doctype: Doctype, position: Position },
+    // `namespaces` holds the bindings in scope on the element, including its own declarations
+    StartElement { name: String, attributes: Attributes, namespace: Option<String>, namespaces: NamespaceScope, position: Position },
+    // `implicit` is set when no end tag was written for the element: <empty/> elements and the
+    // elements the lenient reader closes on its own. The position is then where the element was closed.
+    EndElement { name: String, implicit: bool, position: Position },
+    // The text as written is only kept when asked for in the ParseOptions
+    Text { text: String, raw: Option<String>, position: Position },
+    CData { text: String, position: Position },
+    Comment { comment: String, position: Position },
+    ProcessingInstruction { target: String, data: String, position: Position },
+}
+
+impl XmlEvent {
+    // Where the markup or text of the event starts
+    pub fn get_position(&self) -> Position {
+        match self {
+            XmlEvent::Declaration { position, .. }
+            | XmlEvent::Doctype { position, .. }
+            | XmlEvent::StartElement { position, .. }
+            | XmlEvent::EndElement { position, .. }
+            | XmlEvent::Text { position, .. }
+            | XmlEvent::CData { position, .. }
+            | XmlEvent::Comment { position, .. }
+            | XmlEvent::ProcessingInstruction { position, .. } => *position,
+        }
+    }
+}
+
+struct OpenElement {
+    name: String,
+    namespaces: NamespaceScope,
+    space_preserved: bool,
+}
+
+// Counts the spaces before the first '<' of the line being read, see Node::get_indentation
+#[derive(Default)]
+struct LineIndentation {
+    line: usize,
+    spaces: usize,
+    markup_seen: bool,
+}
+
+impl LineIndentation {
+    // Reads the text of a token that starts on `line` and returns the indentation of that line
+    fn read(&mut self, text: &str, mut line: usize) -> usize {
+        let mut start_line: Option<usize> = None;
+        for c in text.chars() {
+            if line != self.line {
+                *self = LineIndentation { line, spaces: 0, markup_seen: false };
+            }
+            match c {
+                '\n' => line += 1,
+                '<' => self.markup_seen = true,
+                ' ' if !self.markup_seen => self.spaces += 1,
+                _ => (),
+            }
+            start_line.get_or_insert(self.spaces);
+        }
+        start_line.unwrap_or(self.spaces)
+    }
+}
+
+// Pull parser over any buffered reader. Only the token being read is kept in memory,
+// so documents far larger than the available memory can be walked through.
+pub struct XmlReader<R: BufRead> {
+    input: R,
+    options: ParseOptions,
+    // None until the first bytes have shown the encoding
+    decoder: Option<Decoder>,
+    // Decoded input, everything before `consumed` has been turned into events
+    buffer: String,
+    consumed: usize,
+    // Position of buffer[consumed..] in the whole input
+    position: Position,
+    eof: bool,
+    finished: bool,
+    events: VecDeque<XmlEvent>,
+    line_indentation: LineIndentation,
+    indentation: usize,
+    open: Vec<OpenElement>,
+    entities: EntityDecoder,
+    root_closed: bool,
+    seen_element: bool,
+    seen_node: bool,
+    seen_declaration: bool,
+    seen_doctype: bool,
+}
+
+impl<'a> XmlReader<&'a [u8]> {
+    // Reads text that is already decoded, the encoding declaration is not looked at.
+    // A byte order mark at the start is skipped, positions are counted from after it.
+    pub fn from_str(input: &'a str, options: ParseOptions) -> XmlReader<&'a [u8]> {
+        let input = input.strip_prefix('\u{FEFF}').unwrap_or(input);
+        let mut reader = XmlReader::new(input.as_bytes(), options);
+        reader.decoder = Some(Decoder::new(Encoding::Utf8, 0));
+        reader
+    }
+}
+
+impl<R: BufRead> XmlReader<R> {
+    // The encoding is detected the same way as for read_xml_file
+    pub fn new(input: R, options: ParseOptions) -> XmlReader<R> {
+        let entities = EntityDecoder::with_limits(HashMap::new(), options.strict, options.limits);
+        XmlReader {
+            input,
+            options,
+            decoder: None,
+            buffer: String::new(),
+            consumed: 0,
+            position: Position::default(),
+            eof: false,
+            finished: false,
+            events: VecDeque::new(),
+            line_indentation: LineIndentation::default(),
+            indentation: 0,
+            open: Vec::new(),
+            entities,
+            root_closed: false,
+            seen_element: false,
+            seen_node: false,
+            seen_declaration: false,
+            seen_doctype: false,
+        }
+    }
+
+    // Position of the first character that has not been read yet
+    pub fn get_position(&self) -> Position {
+        self.position
+    }
+
+    // Spaces before the first '<' on the line where the token of the last event starts
+    pub fn get_indentation(&self) -> usize {
+        self.indentation
+    }
+
+    // How many elements are open at this point
+    pub fn get_depth(&self) -> usize {
+        self.open.len()
+    }
+
+    pub fn next_event(&mut self) -> Option<Result<XmlEvent, XmlError>> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(Ok(event));
+            }
+            if self.finished {
+                return None;
+            }
+            if let Err(error) = self.read_token() {
+                self.finished = true;
+                return Some(Err(error));
+            }
+        }
+    }
+
+    fn remaining(&self) -> &str {
+        &self.buffer[self.consumed..]
+    }
+
+    fn read_token(&mut self) -> Result<(), XmlError> {
+        while !self.eof && !has_complete_token(self.remaining()) {
+            self.fill()?;
+        }
+        self.process_next_token()
+    }
+
+    // The caller makes sure the buffer starts with a whole token or holds the rest of the input
+    fn process_next_token(&mut self) -> Result<(), XmlError> {
+        let base = self.position;
+        let Some(token) = Tokenizer::with_limits(self.remaining(), self.options.limits).next_token() else {
+            self.finished = true;
+            return self.finish();
+        };
+        let token = token.map_err(|mut error| {
+            if let Some(position) = error.get_position_mut() {
+                *position = position.rebased(base);
+            }
+            error
+        })?;
+        self.indentation = self.line_indentation.read(&self.buffer[self.consumed..self.consumed + token.end.offset], base.line);
+        self.consumed += token.end.offset;
+        self.position = token.end.rebased(base);
+        self.process_token(token.kind, token.start.rebased(base))
+    }
+
+    // Drops the text that has been turned into events already
+    fn compact(&mut self) {
+        self.buffer.drain(..self.consumed);
+        self.consumed = 0;
+    }
+
+    // Reads at least as much as is already buffered, so a long token is scanned only a few times
+    fn fill(&mut self) -> Result<(), XmlError> {
+        self.compact();
+        let wanted = self.buffer.len().max(1);
+        let before = self.buffer.len();
+
+        while !self.eof && self.buffer.len() - before < wanted {
+            let Some(decoder) = self.decoder.as_mut() else {
+                self.detect_encoding()?;
+                continue;
+            };
+            let chunk = self.input.fill_buf()?;
+            let length = chunk.len();
+            self.eof = length == 0;
+            decoder.decode(chunk, self.eof, &mut self.buffer)?;
+            self.input.consume(length);
+        }
+        self.check_document_size()
+    }
+
+    // Looks at the first bytes, up to the end of the XML declaration if there is one
+    fn detect_encoding(&mut self) -> Result<(), XmlError> {
+        let mut start: Vec<u8> = Vec::new();
+        loop {
+            let chunk = self.input.fill_buf()?;
+            let length = chunk.len();
+            start.extend_from_slice(chunk);
+            self.input.consume(length);
+            if length == 0 || shows_encoding(&start) {
+                self.eof = length == 0;
+                break;
+            }
+        }
+        self.start_decoding(&start)
+    }
+
+    fn start_decoding(&mut self, start: &[u8]) -> Result<(), XmlError> {
+        let (encoding, bom_length) = detect_encoding(start)?;
+        let mut decoder = Decoder::new(encoding, bom_length);
+        decoder.decode(&start[bom_length..], self.eof, &mut self.buffer)?;
+        self.decoder = Some(decoder);
+        Ok(())
+    }
+
+    // Counts the decoded text, see Limits::max_document_size
+    fn check_document_size(&self) -> Result<(), XmlError> {
+        let maximum = self.options.limits.max_document_size;
+        if self.position.offset + self.remaining().len() <= maximum {
+            return Ok(());
+        }
+        let mut end = maximum - self.position.offset;
+        while !self.remaining().is_char_boundary(end) {
+            end -= 1;
+        }
+        Err(XmlError::LimitExceeded {
+            limit: Limit::DocumentSize,
+            maximum,
+            position: self.position.advanced_by(&self.remaining()[..end]),
+        })
+    }
+
+    fn process_token(&mut self, kind: TokenKind, start: Position) -> Result<(), XmlError> {
+        if self.options.strict {
+            check_characters(&kind, start)?;
+        }
+        match kind {
+            TokenKind::ProcessingInstruction { target, data } if target == "xml" => self.handle_prolog(&data, start)?,
+            TokenKind::ProcessingInstruction { target, data } => {
+                self.handle_processing_instruction(target, normalize_line_breaks(data), start)?;
+            }
+            TokenKind::Comment(comment) => self.handle_comment(normalize_line_breaks(comment), start)?,
+            TokenKind::Text(text) => {
+                self.check_outside_root(&text, start)?;
+                let decoded = self.entities.decode(&normalize_line_breaks(text.clone()), start)?;
+                self.handle_text(text, decoded, start);
+            }
+            TokenKind::CData(text) => {
+                let text = normalize_line_breaks(text);
+                self.check_outside_root(&text, start)?;
+                if !self.open.is_empty() {
+                    self.push(XmlEvent::CData { text, position: start });
+                }
+            }
+            TokenKind::Doctype(content) => self.handle_doctype(&content, start)?,
+            TokenKind::StartTag { name, attributes, self_closing } => self.handle_start_tag(name, attributes, self_closing, start)?,
+            TokenKind::EndTag { name } => self.handle_end_tag(name, start)?,
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, event: XmlEvent) {
+        self.seen_node |= !matches!(event, XmlEvent::Declaration { .. } | XmlEvent::Doctype { .. });
+        self.events.push_back(event);
+    }
+
+    fn check_outside_root(&self, text: &str, position: Position) -> Result<(), XmlError> {
+        if self.options.strict && self.open.is_empty() && !text.trim().is_empty() {
+            return Err(XmlError::InvalidSyntax {
+                message: "Text is not allowed outside the root element".to_string(),
+                position,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_limits(&self, name: &str, attributes: &[(String, String)], position: Position) -> Result<(), XmlError> {
+        let limits = &self.options.limits;
+        let exceeded = |limit, maximum| Err(XmlError::LimitExceeded { limit, maximum, position });
+        if self.open.len() >= limits.max_element_depth {
+            return exceeded(Limit::ElementDepth, limits.max_element_depth);
+        }
+        if name.len() > limits.max_name_length || attributes.iter().any(|(key, _)| key.len() > limits.max_name_length) {
+            return exceeded(Limit::NameLength, limits.max_name_length);
+        }
+        Ok(())
+    }
+
+    fn check_start_tag(&self, name: &str, attributes: &[(String, String)], position: Position) -> Result<(), XmlError> {
+        if self.root_closed {
+            return Err(XmlError::InvalidSyntax {
+                message: format!("Element <{name}> found after the root element, only one root is allowed"),
+                position,
+            });
+        }
+        check_name(name, position.advanced_by("<"))?;
+        for (key, value) in attributes {
+            check_name(key, position).map_err(|_| XmlError::InvalidAttribute {
+                message: format!("{key} is not a valid attribute name"),
+                position,
+            })?;
+            if let Some(character) = value.chars().find(|&c| c == '<' || !is_xml_char(c)) {
+                return Err(XmlError::InvalidCharacter { character, position });
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_start_tag(&mut self, name: String, attributes: Vec<(String, String)>, self_closing: bool, position: Position) -> Result<(), XmlError> {
+        self.check_limits(&name, &attributes, position)?;
+        if self.options.strict {
+            self.check_start_tag(&name, &attributes, position)?;
+        }
+        let mut decoded_attributes = Attributes::new();
+        for (key, value) in attributes {
+            let decoded = self.entities.decode(&normalize_attribute_value(&value), position)?;
+            let raw = self.options.retain_raw.then_some(value);
+            decoded_attributes.push(Attribute::new(key, decoded, raw));
+        }
+
+        let (parent_scope, parent_space_preserved) = match self.open.last() {
+            Some(parent) => (parent.namespaces.clone(), parent.space_preserved),
+            None => (NamespaceScope::new(), false),
+        };
+        let space_preserved = match decoded_attributes.get("xml:space").map(String::as_str) {
+            Some("preserve") => true,
+            Some("default") => false,
+            Some(value) if self.options.strict => return Err(XmlError::InvalidAttribute {
+                message: format!("xml:space must be default or preserve, found {value}"),
+                position,
+            }),
+            _ => parent_space_preserved,
+        };
+        let (namespace, namespaces) = resolve_namespaces(&name, &mut decoded_attributes, &parent_scope, self.options.strict, position)?;
+
+        self.seen_element = true;
+        self.open.push(OpenElement { name: name.clone(), namespaces: namespaces.clone(), space_preserved });
+        self.push(XmlEvent::StartElement { name: name.clone(), attributes: decoded_attributes, namespace, namespaces, position });
+        if self_closing {
+            self.open.pop();
+            self.push(XmlEvent::EndElement { name, implicit: true, position });
+        }
+        self.root_closed |= self.open.is_empty();
+        Ok(())
+    }
+
+    // Unknown end tags are ignored, otherwise every element opened after the match is closed too
+    fn handle_end_tag(&mut self, name: String, position: Position) -> Result<(), XmlError> {
+        if self.options.strict {
+            check_end_tag(&name, &self.open, position)?;
+        }
+        if let Some(index) = self.open.iter().rposition(|element| element.name == name) {
+            while self.open.len() > index {
+                if let Some(element) = self.open.pop() {
+                    let implicit = self.open.len() != index;
+                    self.push(XmlEvent::EndElement { name: element.name, implicit, position });
+                }
+            }
+        }
+        self.root_closed |= self.open.is_empty();
+        Ok(())
+    }
+
+    // Text outside the root element is dropped
+    fn handle_text(&mut self, raw: String, decoded: String, position: Position) {
+        let Some(parent) = self.open.last() else {
+            return;
+        };
+        let whitespace = if parent.space_preserved { Whitespace::Preserve } else { self.options.whitespace };
+        if let Some(text) = apply_whitespace(decoded, whitespace) {
+            let raw = self.options.retain_raw.then_some(raw);
+            self.push(XmlEvent::Text { text, raw, position });
+        }
+    }
+
+    // Only the first thing in the document may be the XML declaration
+    fn handle_prolog(&mut self, data: &str, position: Position) -> Result<(), XmlError> {
+        let misplaced = self.seen_declaration || self.seen_node || position.offset > 0;
+        if misplaced && self.options.strict {
+            return Err(XmlError::InvalidSyntax {
+                message: "The XML declaration is only allowed at the start of the document".to_string(),
+                position,
+            });
+        }
+        if !self.seen_declaration && !self.seen_node {
+            let declaration = XmlDeclaration::parse(data, position.advanced_by("<?xml"))?;
+            self.seen_declaration = true;
+            self.push(XmlEvent::Declaration { declaration, position });
+        }
+        Ok(())
+    }
+
+    // The entities declared in the internal subset are used for the rest of the document
+    fn handle_doctype(&mut self, content: &str, position: Position) -> Result<(), XmlError> {
+        if self.seen_doctype || self.seen_element {
+            if self.options.strict {
+                return Err(XmlError::InvalidSyntax {
+                    message: "Only one <!DOCTYPE is allowed and it must come before the root element".to_string(),
+                    position,
+                });
+            }
+            return Ok(());
+        }
+        let doctype = Doctype::parse(content, position.advanced_by("<!DOCTYPE"))?;
+        self.entities = EntityDecoder::with_limits(doctype.get_entities().clone(), self.options.strict, self.options.limits);
+        self.seen_doctype = true;
+        self.push(XmlEvent::Doctype { doctype, position });
+        Ok(())
+    }
+
+    // Processing instructions are kept where they appear, inside or outside the root
+    fn handle_processing_instruction(&mut self, target: String, data: String, position: Position) -> Result<(), XmlError> {
+        if target.len() > self.options.limits.max_name_length {
+            return Err(XmlError::LimitExceeded { limit: Limit::NameLength, maximum: self.options.limits.max_name_length, position });
+        }
+        if self.options.strict && target.eq_ignore_ascii_case("xml") {
+            return Err(XmlError::InvalidSyntax {
+                message: format!("The processing instruction target {target} is reserved"),
+                position,
+            });
+        }
+        let data = data.trim_start_matches(is_whitespace).to_string();
+        self.push(XmlEvent::ProcessingInstruction { target, data, position });
+        Ok(())
+    }
+
+    fn handle_comment(&mut self, comment: String, position: Position) -> Result<(), XmlError> {
+        if self.options.strict && (comment.contains("--") || comment.ends_with('-')) {
+            return Err(XmlError::InvalidSyntax {
+                message: "\"--\" is not allowed inside a comment".to_string(),
+                position,
+            });
+        }
+        self.push(XmlEvent::Comment { comment, position });
+        Ok(())
+    }
+
+    // The lenient reader closes whatever is still open at the end of the input
+    fn finish(&mut self) -> Result<(), XmlError> {
+        let end = self.position;
+        if self.options.strict {
+            if let Some(element) = self.open.last() {
+                return Err(XmlError::UnexpectedEof { expected: format!("</{}>", element.name), position: end });
+            }
+            if !self.seen_element {
+                return Err(XmlError::UnexpectedEof { expected: "a root element".to_string(), position: end });
+            }
+        }
+        while let Some(element) = self.open.pop() {
+            self.push(XmlEvent::EndElement { name: element.name, implicit: true, position: end });
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Iterator for XmlReader<R> {
+    type Item = Result<XmlEvent, XmlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event()
+    }
+}
+
+// Parser for input that arrives in pieces, for example from a socket. Every call to feed returns the
+// events that are complete so far, tokens and characters split between chunks are held back until
+// the rest arrives. Once feed or finish has failed, later calls return no events.
+pub struct PushParser {
+    reader: XmlReader<io::Empty>,
+    // The first bytes, held back until they show the encoding
+    start: Vec<u8>,
+}
+
+impl PushParser {
+    pub fn new(options: ParseOptions) -> PushParser {
+        PushParser { reader: XmlReader::new(io::empty(), options), start: Vec::new() }
+    }
+
+    // Position of the first character that has not been turned into an event yet
+    pub fn get_position(&self) -> Position {
+        self.reader.get_position()
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Vec<XmlEvent>, XmlError> {
+        self.read(chunk, false)
+    }
+
+    // Tells the parser that the input is complete and returns the remaining events
+    pub fn finish(&mut self) -> Result<Vec<XmlEvent>, XmlError> {
+        self.read(&[], true)
+    }
+
+    fn read(&mut self, chunk: &[u8], last: bool) -> Result<Vec<XmlEvent>, XmlError> {
+        if self.reader.finished {
+            return Ok(Vec::new());
+        }
+        let result = self.decode(chunk, last).and_then(|_| self.complete_events());
+        if result.is_err() {
+            self.reader.finished = true;
+        }
+        result
+    }
+
+    fn decode(&mut self, chunk: &[u8], last: bool) -> Result<(), XmlError> {
+        let reader = &mut self.reader;
+        reader.compact();
+        reader.eof = last;
+        match reader.decoder.as_mut() {
+            Some(decoder) => decoder.decode(chunk, last, &mut reader.buffer)?,
+            None => {
+                self.start.extend_from_slice(chunk);
+                if !last && !shows_encoding(&self.start) {
+                    return Ok(());
+                }
+                reader.start_decoding(&std::mem::take(&mut self.start))?;
+            }
+        }
+        reader.check_document_size()
+    }
+
+    fn complete_events(&mut self) -> Result<Vec<XmlEvent>, XmlError> {
+        let reader = &mut self.reader;
+        let mut events: Vec<XmlEvent> = Vec::new();
+        loop {
+            events.extend(reader.events.drain(..));
+            if reader.finished || reader.decoder.is_none() || (!reader.eof && !has_complete_token(reader.remaining())) {
+                return Ok(events);
+            }
+            reader.process_next_token()?;
+        }
+    }
+}
+
+// True when enough of the input has been read to detect its encoding, up to the end of the
+// XML declaration if there is one
+fn shows_encoding(start: &[u8]) -> bool {
+    let complete = start.len() >= 4 && (!start.starts_with(b"<?xml") || start.windows(2).any(|pair| pair == b"?>"));
+    complete || start.len() >= 1024
+}
+
+// True when `input` starts with a whole token, text only ends where markup starts
+fn has_complete_token(input: &str) -> bool {
+    if !input.starts_with('<') {
+        return input.contains('<');
+    }
+    for (opener, terminator) in [("<!--", "-->"), ("<![CDATA[", "]]>"), ("<?", "?>")] {
+        if let Some(rest) = input.strip_prefix(opener) {
+            return rest.contains(terminator);
+        }
+        if opener.starts_with(input) {
+            return false;
+        }
+    }
+    if "<!DOCTYPE".starts_with(input) {
+        return false;
+    }
+    if let Some(rest) = input.strip_prefix("<!DOCTYPE") {
+        return find_doctype_end(rest).is_some();
+    }
+
+    // Tags end at the first ">" outside quotes
+    let mut quote: Option<char> = None;
+    for c in input.chars() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return true,
+            _ => (),
+        }
+    }
+    false
+}
+
+// End-of-line handling of XML 1.0 section 2.11: "\r\n" and a lone "\r" become "\n".
+// A "\r" written as a character reference is decoded afterwards and stays.
+fn normalize_line_breaks(text: String) -> String {
+    if text.contains('\r') {
+        text.replace("\r\n", "\n").replace('\r', "\n")
+    } else {
+        text
+    }
+}
+
+// None when the text is dropped
+fn apply_whitespace(text: String, whitespace: Whitespace) -> Option<String> {
+    let whitespace_only = text.chars().all(is_whitespace);
+    match whitespace {
+        Whitespace::Preserve => Some(text),
+        _ if whitespace_only => None,
+        Whitespace::IgnoreWhitespaceOnly => Some(text),
+        Whitespace::Trim => Some(text.trim_matches(is_whitespace).to_string()),
+        Whitespace::Collapse => Some(text.split(is_whitespace).filter(|word| !word.is_empty()).collect::<Vec<&str>>().join(" ")),
+    }
+}
+
+fn check_end_tag(name: &str, open: &[OpenElement], position: Position) -> Result<(), XmlError> {
+    match open.last() {
+        Some(element) if element.name == name => Ok(()),
+        Some(element) => Err(XmlError::MismatchedEndTag {
+            expected: element.name.clone(),
+            found: name.to_string(),
+            position,
+        }),
+        None => Err(XmlError::InvalidSyntax {
+            message: format!("End tag </{name}> has no matching start tag"),
+            position,
+        }),
+    }
+}
+
+// Only the characters of the Char production are allowed, and "]]>" may not appear in character data.
+// Attribute values are checked with the start tag, the declaration and doctype by their own parsers.
+fn check_characters(kind: &TokenKind, start: Position) -> Result<(), XmlError> {
+    let (text, prefix) = match kind {
+        TokenKind::Text(text) => {
+            if let Some(index) = text.find("]]>") {
+                return Err(XmlError::InvalidSyntax {
+                    message: "\"]]>\" is not allowed in character data".to_string(),
+                    position: start.advanced_by(&text[..index]),
+                });
+            }
+            (text, String::new())
+        }
+        TokenKind::CData(text) => (text, "<![CDATA[".to_string()),
+        TokenKind::Comment(comment) => (comment, "<!--".to_string()),
+        TokenKind::ProcessingInstruction { target, data } => (data, format!("<?{target}")),
+        _ => return Ok(()),
+    };
+    match text.char_indices().find(|&(_, c)| !is_xml_char(c)) {
+        Some((index, character)) => Err(XmlError::InvalidCharacter {
+            character,
+            position: start.advanced_by(&prefix).advanced_by(&text[..index]),
+        }),
+        None => Ok(()),
+    }
+}
+
+// `position` is where the name starts, the error points at the offending character
+fn check_name(name: &str, position: Position) -> Result<(), XmlError> {
+    for (index, (byte_index, character)) in name.char_indices().enumerate() {
+        let legal = if index == 0 { is_name_start_char(character) } else { is_name_char(character) };
+        if !legal {
+            return Err(XmlError::InvalidCharacter {
+                character,
+                position: Position {
+                    line: position.line,
+                    column: position.column + index,
+                    offset: position.offset + byte_index,
+                },
+            });
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+    use super::*;
+
+    // A short description of every event, to compare whole streams at once
+    fn describe(events: &[XmlEvent]) -> Vec<String> {
+        events.iter().map(|event| match event {
+            XmlEvent::Declaration { declaration, .. } => format!("decl {}", declaration.get_version()),
+            XmlEvent::Doctype { doctype, .. } => format!("doctype {}", doctype.get_root_name()),
+            XmlEvent::StartElement { name, .. } => format!("start {name}"),
+            XmlEvent::EndElement { name, .. } => format!("end {name}"),
+            XmlEvent::Text { text, .. } => format!("text {text}"),
+            XmlEvent::CData { text, .. } => format!("cdata {text}"),
+            XmlEvent::Comment { comment, .. } => format!("comment {comment}"),
+            XmlEvent::ProcessingInstruction { target, data, .. } => format!("pi {target} {data}"),
+        }).collect()
+    }
+
+    fn read_all<R: BufRead>(reader: XmlReader<R>) -> Result<Vec<XmlEvent>, XmlError> {
+        reader.collect()
+    }
+
+    const DOCUMENT: &str = "<?xml version=\"1.0\"?>\n<!DOCTYPE r [<!ENTITY e \"é>\">]>\n<r a=\"&e;\">\n  <!--c--><?p d?>\n  <x/>t &amp; &e;<![CDATA[<y>]]>\n</r>";
+
+    #[test]
+    fn test_event_stream() {
+        let events = read_all(XmlReader::from_str(DOCUMENT, ParseOptions::strict())).unwrap();
+        assert_eq!(describe(&events), vec![
+            "decl 1.0", "doctype r", "start r", "comment c", "pi p d", "start x", "end x", "text t & é>", "cdata <y>", "end r",
+        ]);
+        let XmlEvent::StartElement { attributes, .. } = &events[2] else { panic!("expected a start element") };
+        assert_eq!(attributes.get("a").unwrap(), "é>");
+        assert_eq!(events[2].get_position(), Position { line: 3, column: 1, offset: 55 });
+        assert_eq!(events[7].get_position(), Position { line: 5, column: 7, offset: 91 });
+    }
+
+    #[test]
+    fn test_tiny_buffer_gives_the_same_events() {
+        let expected = read_all(XmlReader::from_str(DOCUMENT, ParseOptions::strict())).unwrap();
+        let reader = XmlReader::new(BufReader::with_capacity(1, DOCUMENT.as_bytes()), ParseOptions::strict());
+        assert_eq!(read_all(reader).unwrap(), expected);
+
+        let mut utf16 = vec![0xFE, 0xFF];
+        utf16.extend(DOCUMENT.encode_utf16().flat_map(u16::to_be_bytes));
+        let reader = XmlReader::new(BufReader::with_capacity(3, utf16.as_slice()), ParseOptions::strict());
+        assert_eq!(read_all(reader).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_errors_have_absolute_positions() {
+        let xml = "<r>\n  <a>text</a>\n  <b x=1/>\n</r>";
+        let reader = XmlReader::new(BufReader::with_capacity(2, xml.as_bytes()), ParseOptions::default());
+        let error = read_all(reader).unwrap_err();
+        assert_eq!(error, XmlError::InvalidAttribute {
+            message: "value of x must be quoted".to_string(),
+            position: Position { line: 3, column: 8, offset: 25 },
+        });
+
+        let mut reader = XmlReader::from_str("<r><a></b></r>", ParseOptions::strict());
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(reader.next(), Some(Err(XmlError::MismatchedEndTag { .. }))));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_strict_characters() {
+        let error = read_all(XmlReader::from_str("<a>\n x\u{1}</a>", ParseOptions::strict())).unwrap_err();
+        assert_eq!(error, XmlError::InvalidCharacter { character: '\u{1}', position: Position { line: 2, column: 3, offset: 6 } });
+        let error = read_all(XmlReader::from_str("<a>x]]></a>", ParseOptions::strict())).unwrap_err();
+        assert!(matches!(error, XmlError::InvalidSyntax { position: Position { offset: 4, .. }, .. }));
+        for xml in ["<a x=\"\u{1}\"/>", "<a><!--\u{FFFE}--></a>", "<a><?p \u{B}?></a>", "<a><![CDATA[\u{0}]]></a>"] {
+            assert!(matches!(read_all(XmlReader::from_str(xml, ParseOptions::strict())), Err(XmlError::InvalidCharacter { .. })), "{xml:?}");
+        }
+        assert!(read_all(XmlReader::from_str("<a>\u{1}]]></a>", ParseOptions::default())).is_ok());
+        assert!(read_all(XmlReader::from_str("<a x=\"\t\">]] ></a>", ParseOptions::strict())).is_ok());
+    }
+
+    #[test]
+    fn test_line_breaks_are_normalized() {
+        let options = ParseOptions { whitespace: Whitespace::Preserve, retain_raw: true, ..ParseOptions::default() };
+        let xml = "<a>x\r\ny\rz&#13;<!--1\r\n2--><![CDATA[3\r4]]><?p 5\r\n6?></a>";
+        let events = read_all(XmlReader::from_str(xml, options)).unwrap();
+        assert_eq!(describe(&events), vec!["start a", "text x\ny\nz\r", "comment 1\n2", "cdata 3\n4", "pi p 5\n6", "end a"]);
+        let XmlEvent::Text { raw, .. } = &events[1] else { panic!("expected text") };
+        assert_eq!(raw.as_deref(), Some("x\r\ny\rz&#13;"));
+    }
+
+    #[test]
+    fn test_lenient_recovery_closes_elements() {
+        let events = read_all(XmlReader::from_str("<a><b><c></b>text<d>", ParseOptions::default())).unwrap();
+        assert_eq!(describe(&events), vec!["start a", "start b", "start c", "end c", "end b", "text text", "start d", "end d", "end a"]);
+        let implicit: Vec<bool> = events.iter().filter_map(|event| match event {
+            XmlEvent::EndElement { implicit, .. } => Some(*implicit),
+            _ => None,
+        }).collect();
+        assert_eq!(implicit, vec![true, false, true, true]);
+    }
+
+    #[test]
+    fn test_long_stream_keeps_the_buffer_small() {
+        let count = 20_000;
+        let items = std::iter::repeat_n("<item id=\"1\">value</item>\n", count);
+        let xml = std::iter::once("<items>\n").chain(items).chain(std::iter::once("</items>")).collect::<String>();
+        let mut reader = XmlReader::new(BufReader::with_capacity(64, xml.as_bytes()), ParseOptions::strict());
+        let mut starts = 0;
+        while let Some(event) = reader.next_event() {
+            if let XmlEvent::StartElement { .. } = event.unwrap() {
+                starts += 1;
+            }
+            assert!(reader.buffer.len() < 256);
+        }
+        assert_eq!(starts, count + 1);
+    }
+
+    fn feed_in_chunks(bytes: &[u8], size: usize, options: ParseOptions) -> Result<Vec<XmlEvent>, XmlError> {
+        let mut parser = PushParser::new(options);
+        let mut events: Vec<XmlEvent> = Vec::new();
+        for chunk in bytes.chunks(size) {
+            events.extend(parser.feed(chunk)?);
+        }
+        events.extend(parser.finish()?);
+        Ok(events)
+    }
+
+    #[test]
+    fn test_push_parser_gives_the_same_events() {
+        let expected = read_all(XmlReader::from_str(DOCUMENT, ParseOptions::strict())).unwrap();
+        for size in [1, 2, 3, 7, DOCUMENT.len()] {
+            assert_eq!(feed_in_chunks(DOCUMENT.as_bytes(), size, ParseOptions::strict()).unwrap(), expected);
+        }
+
+        let mut latin1 = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><a>caf".to_vec();
+        latin1.extend([0xE9, b'<', b'/', b'a', b'>']);
+        let events = feed_in_chunks(&latin1, 5, ParseOptions::default()).unwrap();
+        assert_eq!(describe(&events), vec!["decl 1.0", "start a", "text café", "end a"]);
+    }
+
+    #[test]
+    fn test_push_parser_returns_events_early() {
+        let mut parser = PushParser::new(ParseOptions::strict());
+        assert!(parser.feed(b"<ro").unwrap().is_empty());
+        assert_eq!(describe(&parser.feed(b"ot><a x='1'/>Fish &am").unwrap()), vec!["start root", "start a", "end a"]);
+        assert_eq!(describe(&parser.feed(b"p; Chips\xE2\x82").unwrap()), Vec::<String>::new());
+        assert_eq!(describe(&parser.feed(b"\xAC</root>").unwrap()), vec!["text Fish & Chips€", "end root"]);
+        assert_eq!(parser.get_position(), Position { line: 1, column: 41, offset: 42 });
+        assert!(parser.finish().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_push_parser_errors() {
+        let mut parser = PushParser::new(ParseOptions::strict());
+        parser.feed(b"<a>\n<b>").unwrap();
+        assert_eq!(parser.finish().unwrap_err(), XmlError::UnexpectedEof {
+            expected: "</b>".to_string(),
+            position: Position { line: 2, column: 4, offset: 7 },
+        });
+        assert!(parser.finish().unwrap().is_empty());
+
+        let mut parser = PushParser::new(ParseOptions::default());
+        parser.feed(b"<a>\xE2\x82").unwrap();
+        assert!(matches!(parser.finish(), Err(XmlError::MalformedEncoding { .. })));
+    }
+
+    #[test]
+    fn test_complete_tokens() {
+        assert!(!has_complete_token("text"));
+        assert!(has_complete_token("text<"));
+        assert!(!has_complete_token("<"));
+        assert!(!has_complete_token("<!-"));
+        assert!(!has_complete_token("<!-- a > b"));
+        assert!(has_complete_token("<!-- a > b -->"));
+        assert!(!has_complete_token("<a x='>'"));
+        assert!(has_complete_token("<a x='>'>"));
+        assert!(!has_complete_token("<!DOCTYPE a [<!ENTITY b 'c'>"));
+        assert!(has_complete_token("<!DOCTYPE a [<!ENTITY b 'c'>]>"));
+        assert!(has_complete_token("<!DOCTYPE a [<!-- it's -->]>"));
+        assert!(!has_complete_token("<!DOCTYPE a [<!-- ]> -->"));
+    }
+
+    #[test]
+    fn test_comments_in_the_internal_subset() {
+        let xml = "<!DOCTYPE a [<!-- it's [ --><?p don't?><!ENTITY e 'x'>]><a>&e;</a>";
+        let expected = vec!["doctype a", "start a", "text x", "end a"];
+        assert_eq!(describe(&read_all(XmlReader::from_str(xml, ParseOptions::strict())).unwrap()), expected);
+        let mut parser = PushParser::new(ParseOptions::strict());
+        assert_eq!(describe(&parser.feed(xml.as_bytes()).unwrap()), expected);
+    }
+}
@@ -0,0 +1,531 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+use crate::attributes::{check_duplicate, read_attribute, ParsedAttribute};
+use crate::error::{Limit, XmlError};
+use crate::options::Limits;
+
+// Position of a character in the input, lines and columns start at 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
+}
+
+impl Position {
+    // Position of the byte `offset` inside `input`
+    pub fn at_offset(input: &str, offset: usize) -> Position {
+        Position::default().advanced_by(&input[..offset])
+    }
+
+    // Position reached after reading `text` starting from this one
+    pub fn advanced_by(&self, text: &str) -> Position {
+        let mut position = *self;
+        for c in text.chars() {
+            position.offset += c.len_utf8();
+            if c == '\n' {
+                position.line += 1;
+                position.column = 1;
+            } else {
+                position.column += 1;
+            }
+        }
+        position
+    }
+
+    // Turns a position counted from `base` instead of from the start of the input into an absolute one
+    pub fn rebased(&self, base: Position) -> Position {
+        Position {
+            line: base.line + self.line - 1,
+            column: if self.line == 1 { base.column + self.column - 1 } else { self.column },
+            offset: base.offset + self.offset,
+        }
+    }
+}
+
+impl Default for Position {
+    fn default() -> Position {
+        Position { line: 1, column: 1, offset: 0 }
+    }
+}
+
+// A stretch of the input, from the first character up to the character after the last one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub start_line: usize,
+    pub start_col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+    pub byte_range: Range<usize>,
+}
+
+impl Span {
+    pub fn new(start: Position, end: Position) -> Span {
+        Span {
+            start_line: start.line,
+            start_col: start.column,
+            end_line: end.line,
+            end_col: end.column,
+            byte_range: start.offset..end.offset,
+        }
+    }
+
+    pub fn get_start(&self) -> Position {
+        Position { line: self.start_line, column: self.start_col, offset: self.byte_range.start }
+    }
+
+    pub fn get_end(&self) -> Position {
+        Position { line: self.end_line, column: self.end_col, offset: self.byte_range.end }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}-{}:{}", self.start_line, self.start_col, self.end_line, self.end_col)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    StartTag { name: String, attributes: Vec<(String, String)>, self_closing: bool },
+    EndTag { name: String },
+    Text(String),
+    Comment(String),
+    // The data is kept as written, including the whitespace that separates it from the target
+    ProcessingInstruction { target: String, data: String },
+    CData(String),
+    // Everything between "<!DOCTYPE" and the closing ">", kept as written
+    Doctype(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub start: Position,
+    pub end: Position,
+}
+
+// Walks the input character by character, line breaks carry no meaning.
+// The attribute limit is checked while a start tag is read, so a huge tag fails early.
+pub struct Tokenizer<'a> {
+    input: &'a str,
+    position: Position,
+    failed: bool,
+    limits: Limits,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(input: &'a str) -> Tokenizer<'a> {
+        Tokenizer::with_limits(input, Limits::default())
+    }
+
+    pub fn with_limits(input: &'a str, limits: Limits) -> Tokenizer<'a> {
+        Tokenizer { input, position: Position::default(), failed: false, limits }
+    }
+
+    pub fn get_position(&self) -> Position {
+        self.position
+    }
+
+    pub fn next_token(&mut self) -> Option<Result<Token, XmlError>> {
+        if self.failed || self.remaining().is_empty() {
+            return None;
+        }
+        let start = self.position;
+        let kind = if self.remaining().starts_with('<') {
+            self.read_markup()
+        } else {
+            Ok(TokenKind::Text(self.read_text()))
+        };
+
+        match kind {
+            Ok(kind) => Some(Ok(Token { kind, start, end: self.position })),
+            Err(error) => {
+                self.failed = true;
+                Some(Err(error))
+            }
+        }
+    }
+
+    fn remaining(&self) -> &'a str {
+        &self.input[self.position.offset..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.remaining().chars().next()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.position.offset += c.len_utf8();
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Some(c)
+    }
+
+    // Consumes `literal` if the input continues with it
+    fn eat(&mut self, literal: &str) -> bool {
+        if self.remaining().starts_with(literal) {
+            for _ in literal.chars() {
+                self.advance();
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(is_whitespace) {
+            self.advance();
+        }
+    }
+
+    fn syntax_error(&self, message: &str) -> XmlError {
+        XmlError::InvalidSyntax { message: message.to_string(), position: self.position }
+    }
+
+    fn unexpected(&self, expected: &str) -> XmlError {
+        match self.peek() {
+            Some(character) => XmlError::InvalidCharacter { character, position: self.position },
+            None => XmlError::UnexpectedEof { expected: expected.to_string(), position: self.position },
+        }
+    }
+
+    fn attribute_error(&self, message: &str) -> XmlError {
+        XmlError::InvalidAttribute { message: message.to_string(), position: self.position }
+    }
+
+    // Reads everything up to `terminator` and consumes the terminator itself
+    fn read_until(&mut self, terminator: &str) -> Result<String, XmlError> {
+        match self.remaining().find(terminator) {
+            Some(index) => {
+                let content = self.remaining()[..index].to_string();
+                while self.position.offset < self.input.len() && !self.remaining().starts_with(terminator) {
+                    self.advance();
+                }
+                self.eat(terminator);
+                Ok(content)
+            }
+            None => {
+                while self.advance().is_some() {}
+                Err(XmlError::UnexpectedEof { expected: format!("\"{terminator}\""), position: self.position })
+            }
+        }
+    }
+
+    fn read_text(&mut self) -> String {
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            if c == '<' {
+                break;
+            }
+            text.push(c);
+            self.advance();
+        }
+        text
+    }
+
+    fn read_name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if is_whitespace(c) || matches!(c, '/' | '>' | '=' | '?' | '<' | '"' | '\'') {
+                break;
+            }
+            name.push(c);
+            self.advance();
+        }
+        name
+    }
+
+    fn read_markup(&mut self) -> Result<TokenKind, XmlError> {
+        if self.eat("<!--") {
+            Ok(TokenKind::Comment(self.read_until("-->")?))
+        } else if self.eat("<![CDATA[") {
+            Ok(TokenKind::CData(self.read_until("]]>")?))
+        } else if self.eat("<!DOCTYPE") {
+            self.read_doctype()
+        } else if self.eat("<?") {
+            self.read_processing_instruction()
+        } else if self.eat("</") {
+            self.read_end_tag()
+        } else if self.remaining().starts_with("<!") {
+            Err(self.syntax_error("Unknown markup declaration"))
+        } else {
+            self.eat("<");
+            self.read_start_tag()
+        }
+    }
+
+    fn read_processing_instruction(&mut self) -> Result<TokenKind, XmlError> {
+        let target = self.read_name();
+        if target.is_empty() {
+            return Err(self.unexpected("a processing instruction target"));
+        }
+        let data = self.read_until("?>")?;
+        Ok(TokenKind::ProcessingInstruction { target, data })
+    }
+
+    fn read_end_tag(&mut self) -> Result<TokenKind, XmlError> {
+        let name = self.read_name();
+        if name.is_empty() {
+            return Err(self.unexpected("an end tag name"));
+        }
+        self.skip_whitespace();
+        if !self.eat(">") {
+            return Err(self.unexpected(&format!("\">\" to close </{name}")));
+        }
+        Ok(TokenKind::EndTag { name })
+    }
+
+    fn read_start_tag(&mut self) -> Result<TokenKind, XmlError> {
+        let name = self.read_name();
+        if name.is_empty() {
+            return Err(self.unexpected("a start tag name"));
+        }
+        let mut attributes: Vec<ParsedAttribute> = Vec::new();
+        let mut names: HashSet<String> = HashSet::new();
+        loop {
+            let before_whitespace = self.position.offset;
+            self.skip_whitespace();
+            let self_closing = self.eat("/>");
+            if self_closing || self.eat(">") {
+                let attributes = attributes.into_iter().map(|attribute| (attribute.name, attribute.value)).collect();
+                return Ok(TokenKind::StartTag { name, attributes, self_closing });
+            }
+            if self.peek().is_none() {
+                return Err(self.unexpected(&format!("\">\" to close <{name}")));
+            }
+            if self.position.offset == before_whitespace {
+                return Err(self.attribute_error("attributes must be separated by whitespace"));
+            }
+            let attribute = self.read_attribute()?;
+            if attributes.len() == self.limits.max_attributes {
+                let maximum = self.limits.max_attributes;
+                return Err(XmlError::LimitExceeded { limit: Limit::Attributes, maximum, position: attribute.name_position });
+            }
+            check_duplicate(&mut names, &attribute)?;
+            attributes.push(attribute);
+        }
+    }
+
+    fn read_attribute(&mut self) -> Result<ParsedAttribute, XmlError> {
+        let (attribute, consumed) = read_attribute(self.remaining(), self.position)?;
+        let end = self.position.offset + consumed;
+        while self.position.offset < end {
+            self.advance();
+        }
+        Ok(attribute)
+    }
+
+    // The doctype can hold an internal subset in brackets, which may itself contain '>'
+    fn read_doctype(&mut self) -> Result<TokenKind, XmlError> {
+        let Some(length) = find_doctype_end(self.remaining()) else {
+            while self.advance().is_some() {}
+            return Err(self.unexpected("\">\" to close <!DOCTYPE"));
+        };
+        let content = self.remaining()[..length].to_string();
+        let end = self.position.offset + length;
+        while self.position.offset < end {
+            self.advance();
+        }
+        self.eat(">");
+        Ok(TokenKind::Doctype(content))
+    }
+}
+
+impl Iterator for Tokenizer<'_> {
+    type Item = Result<Token, XmlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_token()
+    }
+}
+
+// Byte index of the '>' that closes a doctype, `input` starts right after "<!DOCTYPE". Inside the
+// internal subset, quotes, brackets and '>' mean nothing within comments and processing instructions.
+pub fn find_doctype_end(input: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+    let mut index = 0;
+    while let Some(c) = input[index..].chars().next() {
+        let rest = &input[index..];
+        let skipped = [("<!--", "-->"), ("<?", "?>")].into_iter().find(|(opener, _)| rest.starts_with(opener));
+        match (quote, c, skipped) {
+            (Some(q), _, _) if c == q => quote = None,
+            (Some(_), _, _) => (),
+            (None, _, Some((opener, terminator))) if depth > 0 => {
+                index += opener.len() + rest[opener.len()..].find(terminator)? + terminator.len();
+                continue;
+            }
+            (None, '"' | '\'', _) => quote = Some(c),
+            (None, '[', _) => depth += 1,
+            (None, ']', _) => depth = depth.saturating_sub(1),
+            (None, '>', _) if depth == 0 => return Some(index),
+            _ => (),
+        }
+        index += c.len_utf8();
+    }
+    None
+}
+
+pub fn is_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r')
+}
+
+// NameStartChar from the XML 1.0 (fifth edition) grammar
+pub fn is_name_start_char(c: char) -> bool {
+    matches!(c,
+        ':' | 'A'..='Z' | '_' | 'a'..='z'
+        | '\u{C0}'..='\u{D6}' | '\u{D8}'..='\u{F6}' | '\u{F8}'..='\u{2FF}'
+        | '\u{370}'..='\u{37D}' | '\u{37F}'..='\u{1FFF}' | '\u{200C}'..='\u{200D}'
+        | '\u{2070}'..='\u{218F}' | '\u{2C00}'..='\u{2FEF}' | '\u{3001}'..='\u{D7FF}'
+        | '\u{F900}'..='\u{FDCF}' | '\u{FDF0}'..='\u{FFFD}' | '\u{10000}'..='\u{EFFFF}')
+}
+
+pub fn is_name_char(c: char) -> bool {
+    is_name_start_char(c)
+        || matches!(c, '-' | '.' | '0'..='9' | '\u{B7}' | '\u{300}'..='\u{36F}' | '\u{203F}'..='\u{2040}')
+}
+
+// Char production of XML 1.0, anything else may not appear in a document
+pub fn is_xml_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | '\u{20}'..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..='\u{10FFFF}')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_span() {
+        let token = Tokenizer::new("<a>\n  <b/>").nth(2).unwrap().unwrap();
+        let span = Span::new(token.start, token.end);
+        assert_eq!(span, Span { start_line: 2, start_col: 3, end_line: 2, end_col: 7, byte_range: 6..10 });
+        assert_eq!(span.get_start(), token.start);
+        assert_eq!(span.get_end(), token.end);
+        assert_eq!(span.to_string(), "2:3-2:7");
+    }
+
+    fn kinds(input: &str) -> Vec<TokenKind> {
+        Tokenizer::new(input).map(|token| token.unwrap().kind).collect()
+    }
+
+    fn start_tag(name: &str, attributes: &[(&str, &str)], self_closing: bool) -> TokenKind {
+        TokenKind::StartTag {
+            name: name.to_string(),
+            attributes: attributes.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            self_closing,
+        }
+    }
+
+    fn end_tag(name: &str) -> TokenKind {
+        TokenKind::EndTag { name: name.to_string() }
+    }
+
+    #[test]
+    fn test_minified_document() {
+        let tokens = kinds("<a><b/><c x=\"1\">text</c></a>");
+        assert_eq!(tokens, vec![
+            start_tag("a", &[], false),
+            start_tag("b", &[], true),
+            start_tag("c", &[("x", "1")], false),
+            TokenKind::Text("text".to_string()),
+            end_tag("c"),
+            end_tag("a"),
+        ]);
+    }
+
+    #[test]
+    fn test_start_tag_split_across_lines() {
+        let tokens = kinds("<section\n    id=\"1\"\n    name='Intro'\n>");
+        assert_eq!(tokens, vec![start_tag("section", &[("id", "1"), ("name", "Intro")], false)]);
+    }
+
+    #[test]
+    fn test_markup_kinds() {
+        let tokens = kinds("<?xml version=\"1.0\"?><!DOCTYPE r [<!ENTITY e \"x>\">]><!-- a < b --><r><![CDATA[if (a < b) {}]]></r>");
+        assert_eq!(tokens, vec![
+            TokenKind::ProcessingInstruction { target: "xml".to_string(), data: " version=\"1.0\"".to_string() },
+            TokenKind::Doctype(" r [<!ENTITY e \"x>\">]".to_string()),
+            TokenKind::Comment(" a < b ".to_string()),
+            start_tag("r", &[], false),
+            TokenKind::CData("if (a < b) {}".to_string()),
+            end_tag("r"),
+        ]);
+    }
+
+    #[test]
+    fn test_doctype_subset_comments() {
+        let subset = " a [<!-- it's [not] <b> -->\n<?p don't > ?><!ENTITY e 'x>'>]";
+        assert_eq!(kinds(&format!("<!DOCTYPE{subset}><a/>"))[0], TokenKind::Doctype(subset.to_string()));
+        assert_eq!(find_doctype_end(" a [<!-- ] -->]>"), Some(15));
+        assert_eq!(find_doctype_end(" a [<!-- ]> "), None);
+        assert_eq!(find_doctype_end(" a SYSTEM \"<!--x\">"), Some(17));
+    }
+
+    #[test]
+    fn test_attribute_limit() {
+        let limits = Limits { max_attributes: 2, ..Limits::default() };
+        assert!(Tokenizer::with_limits("<a x='1' y='2'/>", limits).next().unwrap().is_ok());
+        let error = Tokenizer::with_limits("<a x='1' y='2' z='3' w='4'/>", limits).next().unwrap().unwrap_err();
+        assert_eq!(error, XmlError::LimitExceeded {
+            limit: Limit::Attributes,
+            maximum: 2,
+            position: Position { line: 1, column: 16, offset: 15 },
+        });
+    }
+
+    #[test]
+    fn test_token_positions() {
+        let tokens: Vec<Token> = Tokenizer::new("<a>\n  <b/>\n</a>").map(|token| token.unwrap()).collect();
+        assert_eq!(tokens[2].start, Position { line: 2, column: 3, offset: 6 });
+        assert_eq!(tokens[2].end, Position { line: 2, column: 7, offset: 10 });
+        assert_eq!(tokens[4].start.line, 3);
+    }
+
+    #[test]
+    fn test_unterminated_comment() {
+        let mut tokenizer = Tokenizer::new("<a><!-- never closed");
+        assert!(tokenizer.next().unwrap().is_ok());
+        assert!(tokenizer.next().unwrap().is_err());
+        assert!(tokenizer.next().is_none());
+    }
+
+    #[test]
+    fn test_unquoted_attribute() {
+        let mut tokenizer = Tokenizer::new("<a x=1>");
+        assert_eq!(tokenizer.next().unwrap().unwrap_err(), XmlError::InvalidAttribute {
+            message: "value of x must be quoted".to_string(),
+            position: Position { line: 1, column: 6, offset: 5 },
+        });
+    }
+
+    #[test]
+    fn test_unexpected_eof_position() {
+        let mut tokenizer = Tokenizer::new("<a>\n<b");
+        assert!(tokenizer.next().unwrap().is_ok());
+        assert!(tokenizer.next().unwrap().is_ok());
+        assert_eq!(tokenizer.next().unwrap().unwrap_err(), XmlError::UnexpectedEof {
+            expected: "\">\" to close <b".to_string(),
+            position: Position { line: 2, column: 3, offset: 6 },
+        });
+    }
+
+    #[test]
+    fn test_invalid_character() {
+        let mut tokenizer = Tokenizer::new("< a>");
+        assert!(matches!(tokenizer.next().unwrap(), Err(XmlError::InvalidCharacter { character: ' ', .. })));
+    }
+
+    #[test]
+    fn test_position_at_offset() {
+        assert_eq!(Position::at_offset("ab\ncd", 4), Position { line: 2, column: 2, offset: 4 });
+    }
+}
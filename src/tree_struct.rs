@@ -1,71 +1,276 @@
-use std::collections::HashMap;
+use std::fmt;
+use crate::attributes::Attributes;
+use crate::error::XmlError;
+use crate::namespaces::{split_qname, NamespaceScope};
+use crate::tokenizer::{Position, Span};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Element,
+    Text,
+    Comment,
+    ProcessingInstruction,
+}
+
+// Handle of a node in the arena of its Document, see Document::get
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    pub fn new(index: usize) -> NodeId {
+        NodeId(index)
+    }
 
+    pub fn get_index(&self) -> usize {
+        self.0
+    }
+}
+
+// Hands out the ids of new nodes in increasing order, an id is never given out twice
+#[derive(Debug, Clone, Default)]
+pub struct NodeIdAllocator {
+    next: usize,
+}
+
+impl NodeIdAllocator {
+    // `next` is the first id that is still free
+    pub fn new(next: usize) -> NodeIdAllocator {
+        NodeIdAllocator { next }
+    }
+
+    pub fn allocate(&mut self) -> NodeId {
+        let id = NodeId(self.next);
+        self.next += 1;
+        id
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug)]
 pub struct Node {
+    kind: NodeKind,
     name: String,
     root: bool,
     leaf: bool,
-    child: Vec<usize>,
-    parent: Option<usize>,
-    attribute: HashMap<String, String>,
+    child: Vec<NodeId>,
+    parent: Option<NodeId>,
+    attribute: Attributes,
+    namespace: Option<String>,
+    namespaces: NamespaceScope,
     inner_element: String,
+    raw_inner_element: Option<String>,
+    cdata: bool,
+    space_preserved: bool,
     indentation : usize,
-    id : usize
+    depth: usize,
+    location: Option<Position>,
+    span: Option<Span>,
+    start_tag_span: Option<Span>,
+    end_tag_span: Option<Span>,
+    id : NodeId
 }
 
 impl Node {
     // Constructor to create a new Node
-    pub fn new(name: String, root: bool, leaf: bool, attribute: HashMap<String, String>, inner_element: Option<String>, indentation:Option<usize>, id:usize) -> Node {
+    pub fn new(name: String, root: bool, leaf: bool, attribute: impl Into<Attributes>, inner_element: Option<String>, indentation:Option<usize>, id:NodeId) -> Node {
         Node {
+            kind: NodeKind::Element,
             name,
             root,
             leaf,
             child: Vec::new(),
             parent: None,
-            attribute,
+            attribute: attribute.into(),
+            namespace: None,
+            namespaces: NamespaceScope::new(),
             inner_element: inner_element.unwrap_or_default(), // Initialize as an empty string
+            raw_inner_element: None,
+            cdata: false,
+            space_preserved: false,
             indentation : indentation.unwrap_or_default(),
+            depth: 0,
+            location: None,
+            span: None,
+            start_tag_span: None,
+            end_tag_span: None,
             id
         }
     }
 
+    // Constructor for a run of character data, a CDATA section becomes its own text node
+    pub fn new_text(text: String, cdata: bool, id: NodeId) -> Node {
+        let mut node = Node::new("#text".to_string(), false, true, Attributes::new(), Some(text), None, id);
+        node.kind = NodeKind::Text;
+        node.cdata = cdata;
+        node
+    }
+
+    // Constructor for a comment, the text between "<!--" and "-->" becomes the inner element
+    pub fn new_comment(comment: String, id: NodeId) -> Node {
+        let mut node = Node::new("#comment".to_string(), false, true, Attributes::new(), Some(comment), None, id);
+        node.kind = NodeKind::Comment;
+        node
+    }
+
+    // Constructor for a processing instruction, the target is stored as the name and the data as the inner element
+    pub fn new_processing_instruction(target: String, data: String, id: NodeId) -> Node {
+        let mut node = Node::new(target, false, true, Attributes::new(), Some(data), None, id);
+        node.kind = NodeKind::ProcessingInstruction;
+        node
+    }
+
+    // The application a processing instruction is addressed to, None for other nodes
+    pub fn target(&self) -> Option<&str> {
+        match self.kind {
+            NodeKind::ProcessingInstruction => Some(&self.name),
+            _ => None,
+        }
+    }
+
+    pub fn data(&self) -> Option<&str> {
+        match self.kind {
+            NodeKind::ProcessingInstruction => Some(&self.inner_element),
+            _ => None,
+        }
+    }
+
+    pub fn get_kind(&self) -> NodeKind {
+        self.kind
+    }
+
+    pub fn is_element(&self) -> bool {
+        self.kind == NodeKind::Element
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.kind == NodeKind::Text
+    }
+
+    pub fn is_comment(&self) -> bool {
+        self.kind == NodeKind::Comment
+    }
+
+    pub fn is_processing_instruction(&self) -> bool {
+        self.kind == NodeKind::ProcessingInstruction
+    }
+
     // Function to set the inner element
     pub fn set_inner_element(&mut self, inner_element: String) {
         self.inner_element = inner_element;
     }
 
-    // Function to get the inner element
+    // For an element the text of its text children with the surrounding whitespace trimmed,
+    // for a text node the text itself
     pub fn get_inner_element(&self) -> &String {
         &self.inner_element
     }
 
-    pub fn get_attribute_value(&self, k: &str) -> Result<String, String> {
-        if self.attribute.contains_key(k) {
-            Ok(self.attribute.get(k).expect("Key should exist").clone())
-        } else {
-            Err(format!("No such key: {} present", k))
+    // The inner element as written in the document, only kept when asked for in the ParseOptions
+    pub fn get_raw_inner_element(&self) -> Option<&String> {
+        self.raw_inner_element.as_ref()
+    }
+
+    pub fn set_raw_inner_element(&mut self, raw_inner_element: String) {
+        self.raw_inner_element = Some(raw_inner_element);
+    }
+
+    pub fn remove_raw_inner_element(&mut self) {
+        self.raw_inner_element = None;
+    }
+
+    // True when the inner element came from a CDATA section and should be written back as one,
+    // an element is marked when one of its text children is a CDATA section
+    pub fn is_cdata(&self) -> bool {
+        self.cdata
+    }
+
+    pub fn set_cdata(&mut self, cdata: bool) {
+        self.cdata = cdata;
+    }
+
+    pub fn get_raw_attribute_value(&self, k: &str) -> Option<&String> {
+        self.attribute.get_attribute(k).and_then(|attribute| attribute.get_raw_value())
+    }
+
+    pub fn get_attribute_value(&self, k: &str) -> Result<String, XmlError> {
+        match self.attribute.get(k) {
+            Some(value) => Ok(value.clone()),
+            None => Err(XmlError::MissingAttribute { name: k.to_string() }),
         }
     }
 
-    pub fn get_all_attributes(&self) -> HashMap<String, String>{
-        self.attribute.clone()
+    // The attributes in the order they were written
+    pub fn get_all_attributes(&self) -> &Attributes {
+        &self.attribute
+    }
+
+    pub fn get_all_attributes_mut(&mut self) -> &mut Attributes {
+        &mut self.attribute
+    }
+
+    pub fn get_prefix(&self) -> Option<&str> {
+        split_qname(&self.name).0
+    }
+
+    pub fn get_local_name(&self) -> &str {
+        split_qname(&self.name).1
+    }
+
+    // The resolved namespace of the element, None when it is in no namespace
+    pub fn get_namespace_uri(&self) -> Option<&String> {
+        self.namespace.as_ref()
+    }
+
+    pub fn set_namespace_uri(&mut self, namespace: Option<String>) {
+        self.namespace = namespace;
+    }
+
+    // The bindings in scope on this element, including the ones declared on its ancestors
+    pub fn get_namespaces(&self) -> &NamespaceScope {
+        &self.namespaces
+    }
+
+    pub fn set_namespaces(&mut self, namespaces: NamespaceScope) {
+        self.namespaces = namespaces;
     }
 
-    pub fn set_child(&mut self, child_id : usize){
+    pub fn set_child(&mut self, child_id : NodeId){
         self.child.push(child_id);
     }
 
-    pub fn get_child(&self) -> &Vec<usize>{
+    // Puts the child at `index` among the children, or last when the index is past the end
+    pub fn insert_child(&mut self, index: usize, child_id: NodeId) {
+        self.child.insert(index.min(self.child.len()), child_id);
+    }
+
+    // False when the node was not a child
+    pub fn remove_child(&mut self, child_id: NodeId) -> bool {
+        let before = self.child.len();
+        self.child.retain(|&child| child != child_id);
+        self.child.len() != before
+    }
+
+    pub fn get_child(&self) -> &Vec<NodeId>{
         &self.child
     }
 
-    pub fn set_parent(&mut self, parent_id : usize){
+    pub fn set_parent(&mut self, parent_id : NodeId){
         self.parent = Some(parent_id);
     }
 
-    pub fn get_parent(&self) -> Option<usize>{
+    pub fn get_parent(&self) -> Option<NodeId>{
         self.parent
     }
 
+    pub fn remove_parent(&mut self) {
+        self.parent = None;
+    }
+
     pub fn get_name(&self) -> &String {
         &self.name
     }
@@ -74,6 +279,10 @@ impl Node {
         self.root
     }
 
+    pub fn set_root(&mut self, root: bool) {
+        self.root = root;
+    }
+
     pub fn is_leaf(&self) -> bool {
         self.leaf
     }
@@ -86,11 +295,73 @@ impl Node {
         self.indentation = amount;
     }
 
-    pub fn get_id(&self) -> usize{
+    // True when xml:space="preserve" applies to the element, set on it or inherited from an ancestor
+    pub fn is_space_preserved(&self) -> bool {
+        self.space_preserved
+    }
+
+    pub fn set_space_preserved(&mut self, space_preserved: bool) {
+        self.space_preserved = space_preserved;
+    }
+
+    // How many elements enclose the node, 0 for the root and anything outside it
+    pub fn get_depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+
+    // Where the node starts in the source, None for nodes that were not parsed from a file
+    pub fn get_location(&self) -> Option<Position> {
+        self.location
+    }
+
+    pub fn set_location(&mut self, location: Position) {
+        self.location = Some(location);
+    }
+
+    // Where the whole node lives in the source, for an element from the start of its start tag to the end of its end tag
+    pub fn get_span(&self) -> Option<&Span> {
+        self.span.as_ref()
+    }
+
+    pub fn set_span(&mut self, span: Span) {
+        self.span = Some(span);
+    }
+
+    pub fn get_start_tag_span(&self) -> Option<&Span> {
+        self.start_tag_span.as_ref()
+    }
+
+    pub fn set_start_tag_span(&mut self, span: Span) {
+        self.start_tag_span = Some(span);
+    }
+
+    // None for <empty/> elements and for nodes that are not elements
+    pub fn get_end_tag_span(&self) -> Option<&Span> {
+        self.end_tag_span.as_ref()
+    }
+
+    pub fn set_end_tag_span(&mut self, span: Span) {
+        self.end_tag_span = Some(span);
+    }
+
+    // Everything between the start tag and the end tag of an element
+    pub fn get_content_span(&self) -> Option<Span> {
+        match (&self.start_tag_span, &self.end_tag_span) {
+            (Some(start_tag), Some(end_tag)) => Some(Span::new(start_tag.get_end(), end_tag.get_start())),
+            _ => None,
+        }
+    }
+
+    // The id stays the same when the source is reformatted, it does not depend on line numbers
+    pub fn get_id(&self) -> NodeId{
         self.id
     }
 
-    pub fn set_id(&mut self, new_id : usize){
+    pub fn set_id(&mut self, new_id : NodeId){
         self.id = new_id;
     }
 
@@ -98,8 +369,8 @@ impl Node {
 
 
 #[cfg(test)]
-
 mod test_tree {
+    use std::collections::HashMap;
     use std::vec;
 
     use super::*;
@@ -114,20 +385,20 @@ mod test_tree {
     #[test]
     fn create_node() {
         let attributes = create_attributes("10533", "Bob Ross");
-        let a_node: Node = Node::new("Root Node".to_string(), true, false, attributes, None,None, 0);
-        assert_eq!(a_node.root, true);
-        assert_eq!(a_node.leaf, false);
+        let a_node: Node = Node::new("Root Node".to_string(), true, false, attributes, None,None, NodeId::new(0));
+        assert!(a_node.root);
+        assert!(!a_node.leaf);
         assert_eq!(a_node.name, "Root Node");
     }
 
     #[test]
     fn no_such_attribute() {
         let attributes = create_attributes("10533", "Bob Ross");
-        let a_node: Node = Node::new("Some Node".to_string(), true, false, attributes, None, None, 0);
+        let a_node: Node = Node::new("Some Node".to_string(), true, false, attributes, None, None, NodeId::new(0));
 
         assert!(a_node.get_attribute_value("Release Date").is_err());
         assert_eq!(
-            a_node.get_attribute_value("Release Date").err().unwrap(),
+            a_node.get_attribute_value("Release Date").err().unwrap().to_string(),
             "No such key: Release Date present"
         );
     }
@@ -135,7 +406,7 @@ mod test_tree {
     #[test]
     fn get_node_attributes() {
         let attributes = create_attributes("10533", "Bob Ross");
-        let a_node: Node = Node::new("Some Node".to_string(), true, false, attributes, None, None, 1);
+        let a_node: Node = Node::new("Some Node".to_string(), true, false, attributes, None, None, NodeId::new(1));
 
         assert_eq!(a_node.get_attribute_value("ID").unwrap(), "10533");
         assert_eq!(a_node.get_attribute_value("Author").unwrap(), "Bob Ross");
@@ -144,14 +415,14 @@ mod test_tree {
     #[test]
     fn get_node_root() {
         let attributes = create_attributes("10533", "Bob Ross");
-        let a_node: Node = Node::new("Root Node".to_string(), true, false, attributes, None, None, 1);
+        let a_node: Node = Node::new("Root Node".to_string(), true, false, attributes, None, None, NodeId::new(1));
         assert!(a_node.is_root());
     }
 
     #[test]
     fn get_node_leaf() {
         let attributes = create_attributes("10533", "Bob Ross");
-        let a_node: Node = Node::new("Leaf Node".to_string(), true, false, attributes, None, None, 1);
+        let a_node: Node = Node::new("Leaf Node".to_string(), true, false, attributes, None, None, NodeId::new(1));
         assert!(!a_node.is_leaf());
     }
 
@@ -161,14 +432,14 @@ mod test_tree {
         let attributes_2 = create_attributes("10532", "Mike Ross");
         let attributes_3 = create_attributes("10534", "DC");
 
-        let mut a_node: Node = Node::new("Parent Node".to_string(), true, false, attributes_1, None, None, 1); // Parent
-        let b_node: Node = Node::new("Child Node 1".to_string(), false, true, attributes_2, None, None, 2); // Child 1
-        let c_node: Node = Node::new("Child Node 2".to_string(), false, true, attributes_3, None, None, 3); // Child 2
+        let mut a_node: Node = Node::new("Parent Node".to_string(), true, false, attributes_1, None, None, NodeId::new(1)); // Parent
+        let b_node: Node = Node::new("Child Node 1".to_string(), false, true, attributes_2, None, None, NodeId::new(2)); // Child 1
+        let c_node: Node = Node::new("Child Node 2".to_string(), false, true, attributes_3, None, None, NodeId::new(3)); // Child 2
 
         a_node.set_child(b_node.get_id());
         a_node.set_child(c_node.get_id());
 
-        assert_eq!(*a_node.get_child(), vec![2,3]);
+        assert_eq!(*a_node.get_child(), vec![NodeId::new(2), NodeId::new(3)]);
     }
 
     #[test]
@@ -176,17 +447,30 @@ mod test_tree {
         let attributes_1 = create_attributes("10533", "Bob Ross");
         let attributes_2 = create_attributes("10532", "Mike Ross");
 
-        let a_node: Node = Node::new("Parent Node".to_string(), true, false, attributes_1, None, None, 1); // Parent
-        let mut b_node: Node = Node::new("Child Node".to_string(), false, true, attributes_2, None, None, 2); // Child
+        let a_node: Node = Node::new("Parent Node".to_string(), true, false, attributes_1, None, None, NodeId::new(1)); // Parent
+        let mut b_node: Node = Node::new("Child Node".to_string(), false, true, attributes_2, None, None, NodeId::new(2)); // Child
 
         b_node.set_parent(a_node.get_id());
+        assert_eq!(b_node.get_parent(), Some(NodeId::new(1)));
 
     }
 
+    #[test]
+    fn insert_and_remove_children() {
+        let mut node = Node::new("Parent Node".to_string(), true, false, HashMap::new(), None, None, NodeId::new(0));
+        node.set_child(NodeId::new(1));
+        node.insert_child(0, NodeId::new(2));
+        node.insert_child(5, NodeId::new(3));
+        assert_eq!(*node.get_child(), vec![NodeId::new(2), NodeId::new(1), NodeId::new(3)]);
+        assert!(node.remove_child(NodeId::new(1)));
+        assert!(!node.remove_child(NodeId::new(1)));
+        assert_eq!(*node.get_child(), vec![NodeId::new(2), NodeId::new(3)]);
+    }
+
     #[test]
     fn inner_element_operations() {
         let attributes = create_attributes("10533", "Bob Ross");
-        let mut node: Node = Node::new("Root Node".to_string(), true, false, attributes, None, None, 1);
+        let mut node: Node = Node::new("Root Node".to_string(), true, false, attributes, None, None, NodeId::new(1));
 
         // Initially, the inner element should be empty
         assert_eq!(node.get_inner_element(), "");
@@ -195,4 +479,46 @@ mod test_tree {
         node.set_inner_element("This is the inner element.".to_string());
         assert_eq!(node.get_inner_element(), "This is the inner element.");
     }
+
+    #[test]
+    fn allocate_ids() {
+        let mut allocator = NodeIdAllocator::default();
+        assert_eq!(allocator.allocate(), NodeId::new(0));
+        assert_eq!(allocator.allocate(), NodeId::new(1));
+        assert_eq!(NodeIdAllocator::new(7).allocate().to_string(), "7");
+    }
+
+    #[test]
+    fn comment_node() {
+        let node = Node::new_comment(" A comment ".to_string(), NodeId::new(4));
+        assert!(node.is_comment());
+        assert!(!node.is_element());
+        assert_eq!(node.get_kind(), NodeKind::Comment);
+        assert_eq!(node.get_name(), "#comment");
+        assert_eq!(node.get_inner_element(), " A comment ");
+        assert_eq!(node.get_id(), NodeId::new(4));
+        assert_eq!(node.get_location(), None);
+    }
+
+    #[test]
+    fn processing_instruction_node() {
+        let node = Node::new_processing_instruction("xml-stylesheet".to_string(), "href=\"a.xsl\"".to_string(), NodeId::new(2));
+        assert!(node.is_processing_instruction());
+        assert_eq!(node.target(), Some("xml-stylesheet"));
+        assert_eq!(node.data(), Some("href=\"a.xsl\""));
+
+        let comment = Node::new_comment("text".to_string(), NodeId::new(3));
+        assert_eq!(comment.target(), None);
+        assert_eq!(comment.data(), None);
+    }
+
+    #[test]
+    fn text_node() {
+        let a_node = Node::new_text("Some text".to_string(), true, NodeId::new(4));
+        assert!(a_node.is_text());
+        assert!(a_node.is_cdata());
+        assert_eq!(a_node.get_name(), "#text");
+        assert_eq!(a_node.get_inner_element(), "Some text");
+        assert_eq!(a_node.target(), None);
+    }
 }
@@ -1,51 +1,69 @@
 use std::{collections::HashMap,fs};
-use crate::tree_struct::Node;
-
-
-pub fn read_xml_file(file_name: &str) -> Result<String, String> {
-    match fs::read_to_string(file_name) {
-        Ok(data) => Ok(data),
-        Err(_) => Err("No Such File Found".to_string()),
-    }
+use std::io::{BufRead, BufReader, Read};
+use crate::tree_struct::{Node, NodeId, NodeIdAllocator};
+use crate::tokenizer::{is_whitespace, Position, Span};
+use crate::options::ParseOptions;
+use crate::encoding::decode_bytes;
+use crate::reader::{XmlEvent, XmlReader};
+use crate::attributes::{is_space_preserved, Attributes, parse_attributes};
+use crate::doctype::Doctype;
+use crate::document::{Document, XmlDeclaration};
+use crate::error::XmlError;
+
+
+// The file is decoded from its byte order mark or declared encoding, see encoding.rs
+pub fn read_xml_file(file_name: &str) -> Result<String, XmlError> {
+    decode_bytes(&fs::read(file_name)?)
 }
 
-pub fn print_content(file_name: &str){
-    let file_contents: String = read_xml_file(file_name).unwrap();
+pub fn print_content(file_name: &str) -> Result<(), XmlError>{
+    let file_contents: String = read_xml_file(file_name)?;
     for line in file_contents.lines(){
-        let line = line.trim().replace("?", "");
-        let start = line.find('<').unwrap()+1;
-        let end = line.find('>').unwrap();
-        println!("{},{},{}", start, end, &line[start..end]);
+        let tag = trim_line(&get_first_tag(line)?);
+        println!("{}", tag);
     }
+    Ok(())
 }
 
-pub fn is_prolog(prolog: &String) -> bool {
-    prolog.contains("<?") & prolog.contains("?>")
+// Only the <?xml ...?> declaration is the prolog, other processing instructions are not
+pub fn is_prolog(prolog: &str) -> bool {
+    let prolog = prolog.trim_start();
+    prolog.strip_prefix("<?xml").is_some_and(|rest| rest.starts_with(is_whitespace)) && prolog.contains("?>")
 }
 
-pub fn is_comment(comment: &String) -> bool{
+pub fn is_comment(comment: &str) -> bool{
     comment.contains("<!--") & comment.contains("-->")
 }
 
-pub fn is_newline_inner_element(line: &String) -> bool{
-    (line.len() > 0) && !(line.contains("<") || line.contains(">") || line.contains("/"))
+pub fn is_newline_inner_element(line: &str) -> bool{
+    !(line.is_empty() || line.contains('<') || line.contains('>') || line.contains('/'))
 }
 
-pub fn get_first_tag(line: &String) -> String{
-    let f_i = line.find("<").unwrap();
-    let s_i= line.find(">").unwrap()+1;
-    line[f_i..s_i].to_string()
+pub fn get_first_tag(line: &str) -> Result<String, XmlError>{
+    let f_i = line.find('<').ok_or_else(|| XmlError::UnexpectedEof {
+        expected: "\"<\"".to_string(),
+        position: Position::at_offset(line, line.len()),
+    })?;
+    let s_i = match line[f_i..].find('>') {
+        Some(index) => f_i + index + 1,
+        None => return Err(XmlError::UnexpectedEof {
+            expected: "\">\"".to_string(),
+            position: Position::at_offset(line, line.len()),
+        }),
+    };
+    Ok(line[f_i..s_i].to_string())
 }
 
-pub fn get_inner_element(line: &String) -> String{
+pub fn get_inner_element(line: &str) -> String{
     match line.find("</") {
         Some(index) => line[..index].to_string(),
-        None => line.clone()
+        None => line.to_string()
     }
 }
 
-pub fn trim_line(line: &String) -> String {
-    line.replace("?", "")
+pub fn trim_line(line: &str) -> String {
+    line.replace("<?", "")
+        .replace("?>", "")
         .replace("/>", "")
         .replace("</", "")
         .replace("<", "") 
@@ -54,12 +72,12 @@ pub fn trim_line(line: &String) -> String {
 }
 
 
-pub fn find_name(line: &String) -> String{
+pub fn find_name(line: &str) -> String{
     let line = trim_line(line);
     match line.find(" ") {
         Some(name_end_i) => String::from(&line[0..name_end_i]),
         None =>{
-            if line.len() > 0 {
+            if !line.is_empty() {
                 trim_line(&line)
             }else {
                 "ERROR!".to_string()
@@ -68,163 +86,211 @@ pub fn find_name(line: &String) -> String{
     }
 }
 
-pub fn find_attributes(line: &String) -> HashMap<String, String> {
-    let mut result: HashMap<String, String> = HashMap::new();
-    let mut proc_string: String = line.clone(); 
+pub fn find_attributes(line: &str) -> Result<Attributes, XmlError> {
+    let parsed = parse_attributes(line, Position::default())?;
+    Ok(parsed.into_iter().map(|attribute| (attribute.name, attribute.value)).collect())
+}
 
-    loop {
-        if proc_string.len() < 5{
-            break;
-        }
-        let equal_i: usize = proc_string.find('=').unwrap();
-        let key: String = proc_string[1..equal_i].to_string().replace(" ", "");
-        proc_string.replace_range(..equal_i+2, "");
+pub fn process_line_list(lines: &[String]) -> Result<Vec<Node>, XmlError> {
+    process_line_list_with_options(lines, &ParseOptions::default())
+}
 
-        let value_i : usize = proc_string.find('"').unwrap();
-        let value : String = proc_string[..value_i].to_string();
+pub fn process_line_list_with_options(lines: &[String], options: &ParseOptions) -> Result<Vec<Node>, XmlError> {
+    Ok(parse_document(lines, options)?.into_nodes())
+}
 
-        proc_string.replace_range(..value_i, "");
-        result.insert(key.to_string(), value.to_string());
-    }
-    result
+pub fn parse_document(lines: &[String], options: &ParseOptions) -> Result<Document, XmlError> {
+    parse_str(&lines.join("\n"), options)
 }
 
-pub fn process_line_list(lines: &Vec<String>) -> Vec<Node> {
-    let mut processing_nodes: Vec<(String,usize)> = Vec::new(); 
-    let mut all_nodes: Vec<Node> = Vec::new(); 
-
-    for (id,line) in lines.iter().enumerate() {
-        if is_prolog(line) {
-            handle_prolog(line);
-        } else if is_comment(line) {
-            handle_comment(line);
-        } else if is_newline_inner_element(line) {
-            handle_inner_element(line, &mut all_nodes);
-        } else {
-            process_node(line, &mut processing_nodes, &mut all_nodes, id);
-        }
-    }
+// A byte order mark at the start is skipped, as it is for files
+pub fn parse_str(contents: &str, options: &ParseOptions) -> Result<Document, XmlError> {
+    build_document(XmlReader::from_str(contents, options.clone()), options)
+}
 
-    all_nodes
+// The encoding is detected by XmlReader::new
+pub fn parse_bytes(bytes: &[u8], options: &ParseOptions) -> Result<Document, XmlError> {
+    parse_reader(bytes, options)
 }
 
-pub fn tree_id_to_node(all_nodes : &Vec<Node>) -> HashMap<usize, &Node>{
+// The input is decoded while it is read, never more than the max_document_size limit allows
+pub fn parse_reader<R: Read>(reader: R, options: &ParseOptions) -> Result<Document, XmlError> {
+    build_document(XmlReader::new(BufReader::new(reader), options.clone()), options)
+}
 
-    let mut result : HashMap<usize, &Node> = HashMap::new();
+fn build_document<R: BufRead>(mut reader: XmlReader<R>, options: &ParseOptions) -> Result<Document, XmlError> {
+    let mut builder = TreeBuilder::new(options);
 
-    for node in all_nodes{
-        result.insert(node.get_id(), &node);
+    // The reader stops right after the token of the event it returns, which is where the event ends
+    while let Some(event) = reader.next_event() {
+        builder.process_event(event?, reader.get_position(), reader.get_indentation());
     }
 
-    result
+    Ok(builder.finish())
 }
 
-fn handle_prolog(line: &String) {
-    println!("Prolog detected: {}", line);
-}
+pub fn tree_id_to_node(all_nodes : &[Node]) -> HashMap<NodeId, &Node>{
 
-fn handle_comment(line: &String) {
-    println!("Comment detected: {}", line);
-}
+    let mut result : HashMap<NodeId, &Node> = HashMap::new();
 
-fn handle_inner_element(line: &String, all_nodes: &mut Vec<Node>) {
-    if let Some(parent_node) = all_nodes.last_mut() {
-        let inner_text = line.trim_start().to_string();
-        parent_node.set_inner_element(inner_text);
+    for node in all_nodes{
+        result.insert(node.get_id(), node);
     }
-}
 
-fn process_node(line: &String, processing_nodes: &mut Vec<(String,usize)>, all_nodes: &mut Vec<Node>, node_id : usize) {
-    let indentation = calculate_indentation(line);
-    let trimmed_line = line.trim_start().to_string();
+    result
+}
 
-    let first_tag = &get_first_tag(&trimmed_line);
-    let node_name = find_name(&first_tag);
+// Builds the list of nodes from the events of an XmlReader, the reader has already checked the document
+struct TreeBuilder<'a> {
+    options: &'a ParseOptions,
+    // Place in all_nodes of the elements that are open
+    processing_nodes: Vec<usize>,
+    all_nodes: Vec<Node>,
+    ids: NodeIdAllocator,
+    declaration: Option<XmlDeclaration>,
+    doctype: Option<Doctype>,
+}
 
-    let mut root : bool = false;
-    let mut inner_element = None;
+impl<'a> TreeBuilder<'a> {
+    fn new(options: &'a ParseOptions) -> TreeBuilder<'a> {
+        TreeBuilder {
+            options,
+            processing_nodes: Vec::new(),
+            all_nodes: Vec::new(),
+            ids: NodeIdAllocator::default(),
+            declaration: None,
+            doctype: None,
+        }
+    }
 
-    match processing_nodes.last() {
-        Some(current) => {
-            if current.0 == node_name{
-                processing_nodes.pop();
-                return;
-            }else {
-                processing_nodes.push((node_name.clone(), node_id));
+    // Every node gets the next id, so the id of a node is also its place in all_nodes
+    // `indentation` is the one of the line where the event starts
+    fn process_event(&mut self, event: XmlEvent, end: Position, indentation: usize) {
+        let position = event.get_position();
+        let span = Span::new(position, end);
+        match event {
+            XmlEvent::Declaration { declaration, .. } => self.declaration = Some(declaration),
+            XmlEvent::Doctype { doctype, .. } => self.doctype = Some(doctype),
+            XmlEvent::StartElement { name, attributes, namespace, namespaces, .. } => {
+                let parent_space_preserved = self.processing_nodes.last().is_some_and(|&parent_index| self.all_nodes[parent_index].is_space_preserved());
+                let space_preserved = is_space_preserved(&attributes, parent_space_preserved);
+                let mut node = Node::new(name, self.processing_nodes.is_empty(), false, attributes, None, Some(indentation), self.ids.allocate());
+                node.set_namespace_uri(namespace);
+                node.set_namespaces(namespaces);
+                node.set_space_preserved(space_preserved);
+                node.set_start_tag_span(span.clone());
+                self.add_node(node, span);
+                self.processing_nodes.push(self.all_nodes.len() - 1);
+            }
+            // <empty/> elements and elements closed by the lenient reader have no end tag
+            XmlEvent::EndElement { implicit, .. } => {
+                if let Some(index) = self.processing_nodes.pop() {
+                    let node = &mut self.all_nodes[index];
+                    let start = node.get_location().unwrap_or_default();
+                    if !implicit {
+                        node.set_end_tag_span(span);
+                    }
+                    // An element closed by recovery ends where the markup that closed it starts
+                    let end = if implicit && position != start { position } else { end };
+                    node.set_span(Span::new(start, end));
+                }
+            }
+            // Character data becomes a text node among the children of the open element
+            XmlEvent::Text { text, raw, .. } => {
+                let mut node = Node::new_text(text, false, self.ids.allocate());
+                if let Some(raw) = raw {
+                    node.set_raw_inner_element(raw);
+                }
+                self.add_node(node, span);
+            }
+            // CDATA content is kept verbatim, including surrounding whitespace
+            XmlEvent::CData { text, .. } => {
+                let mut node = Node::new_text(text.clone(), true, self.ids.allocate());
+                if self.options.retain_raw {
+                    node.set_raw_inner_element(text);
+                }
+                self.add_node(node, span);
+            }
+            // Comments and processing instructions outside the root have no parent
+            XmlEvent::Comment { comment, .. } => {
+                let node = Node::new_comment(comment, self.ids.allocate());
+                self.add_node(node, span);
+            }
+            XmlEvent::ProcessingInstruction { target, data, .. } => {
+                let node = Node::new_processing_instruction(target, data, self.ids.allocate());
+                self.add_node(node, span);
             }
         }
-        None => {
-            root = true;
-            processing_nodes.push((node_name.clone(), node_id));
-        }
-        
     }
 
-    let line_remaining = &trimmed_line.replace(first_tag, "");
-    let trimed_first_tag = trim_line(&first_tag.replace(&node_name, ""));
-    let attributes = find_attributes(&trimed_first_tag);    
-
-    if !line_remaining.is_empty() {
-        inner_element = extract_inner_element(&line_remaining, &node_name);
-        processing_nodes.pop();
+    fn add_node(&mut self, mut node: Node, span: Span) {
+        node.set_location(span.get_start());
+        node.set_span(span);
+        set_relation(&self.processing_nodes, &mut self.all_nodes, node);
     }
 
-    if first_tag.contains("/>"){
-        processing_nodes.pop();
+    fn finish(self) -> Document {
+        let mut all_nodes = self.all_nodes;
+        collect_inner_elements(&mut all_nodes);
+        Document::new(self.declaration, self.doctype, all_nodes)
     }
-
-    let mut node = Node::new(
-        node_name,
-        root,
-        false,
-        attributes,
-        inner_element,
-        Some(indentation),
-        node_id
-    );
-
-    set_relation(processing_nodes, all_nodes, &mut node);
-    
-    all_nodes.push(node);
-
-
 }
 
-fn set_relation(processing_nodes: &mut Vec<(String, usize)>, all_nodes: &mut Vec<Node>, current_node: &mut Node){
-    
-    let mut wanted_id : usize = 0;
-
-    match processing_nodes.last() {
-        Some(a_node) => {
-            if processing_nodes.len() > 1 && a_node.1 == current_node.get_id(){
-                wanted_id = processing_nodes[processing_nodes.len()-2].1;
-            }
-            else {
-                wanted_id = a_node.1;
-            }
-        },
-        None => return
+fn set_relation(processing_nodes: &[usize], all_nodes: &mut Vec<Node>, mut current_node: Node){
+    current_node.set_depth(processing_nodes.len());
+    if let Some(parent_index) = processing_nodes.last() {
+        let parent = &mut all_nodes[*parent_index];
+        current_node.set_parent(parent.get_id());
+        parent.set_child(current_node.get_id());
     }
+    all_nodes.push(current_node);
+}
 
-    for node in all_nodes{
-        if node.get_id() == wanted_id{
-            current_node.set_parent(node.get_id());
-            node.set_child(current_node.get_id());
-            return
+// Fills in the inner element of every element from its text children, the nodes are in document order
+// so the parent of a node is the last element seen one level up
+fn collect_inner_elements(all_nodes: &mut [Node]) {
+    let mut open: Vec<usize> = Vec::new();
+    let mut text_children: Vec<Vec<usize>> = vec![Vec::new(); all_nodes.len()];
+    for (index, node) in all_nodes.iter().enumerate() {
+        open.truncate(node.get_depth());
+        if node.is_element() {
+            open.push(index);
+        } else if let (true, Some(&parent)) = (node.is_text(), open.last()) {
+            text_children[parent].push(index);
         }
     }
 
+    for (index, children) in text_children.into_iter().enumerate() {
+        if children.is_empty() {
+            continue;
+        }
+        let texts: Vec<&Node> = children.iter().map(|&child| &all_nodes[child]).collect();
+        let (inner_element, raw_inner_element, cdata) = join_text_children(&texts);
+
+        let node = &mut all_nodes[index];
+        node.set_inner_element(inner_element);
+        node.set_cdata(cdata);
+        if let Some(raw_inner_element) = raw_inner_element {
+            node.set_raw_inner_element(raw_inner_element);
+        }
+    }
 }
 
-fn calculate_indentation(line: &String) -> usize {
-    line.find('<')
-        .map(|index| line[..index].chars().filter(|&c| c == ' ').count())
-        .unwrap_or(0)
-}
-
-fn extract_inner_element(line: &String, node_name : &String) -> Option<String> {
-    let closing_tag = format!("</{node_name}");
-    line.find(&closing_tag).map(|index| line[..index].to_string())
+// The inner element of an element made from its text children: the joined text, the joined raw text
+// when every child kept it, and whether one of them is a CDATA section
+pub(crate) fn join_text_children(texts: &[&Node]) -> (String, Option<String>, bool) {
+    if texts.is_empty() {
+        return (String::new(), None, false);
+    }
+    let join = |raw: bool| -> Option<String> {
+        let mut joined = String::new();
+        for text in texts {
+            joined.push_str(if raw { text.get_raw_inner_element()? } else { text.get_inner_element() });
+        }
+        Some(joined)
+    };
+    let cdata = texts.iter().any(|text| text.is_cdata());
+    (join(false).unwrap_or_default(), join(true), cdata)
 }
 
 
@@ -233,6 +299,8 @@ mod tests {
 
     use std::{collections::HashMap, vec};
 
+    use crate::error::Limit;
+    use crate::options::{Limits, Whitespace};
     use super::*;
 
     fn example_xml() -> Vec<String> {
@@ -253,17 +321,77 @@ mod tests {
         ]
     }
 
+    fn elements(nodes: &[Node]) -> Vec<&Node> {
+        nodes.iter().filter(|node| node.is_element()).collect()
+    }
+
     #[test]
     fn test_read_xml_file() {
-        let file_name = "src/exampleXML.xml";
+        let file_name = "tests/fixtures/exampleXML.xml";
         let file_contents: String = read_xml_file(file_name).unwrap();
         assert_eq!(file_contents, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\r\n");
     }
     
+    #[test]
+    fn test_read_utf16_file() {
+        let path = std::env::temp_dir().join(format!("xml_proc_utf16_{}.xml", std::process::id()));
+        let text = "<?xml version=\"1.0\" encoding=\"UTF-16\"?>\r\n<a>Grüße</a>";
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        fs::write(&path, bytes).unwrap();
+
+        let file_contents = read_xml_file(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        let file_contents = file_contents.unwrap();
+        assert!(is_prolog(&file_contents));
+        let lines: Vec<String> = file_contents.lines().map(String::from).collect();
+        let document = parse_document(&lines, &ParseOptions::strict()).unwrap();
+        assert_eq!(document.get_declaration().unwrap().get_encoding().unwrap(), "UTF-16");
+        assert_eq!(document.get_nodes()[0].get_inner_element(), "Grüße");
+    }
+
+    #[test]
+    fn test_parse_from_memory(){
+        let contents = example_xml().join("\n");
+        let from_lines = parse_document(&example_xml(), &ParseOptions::strict()).unwrap();
+        let from_str = parse_str(&contents, &ParseOptions::strict()).unwrap();
+        let from_bytes = parse_bytes(contents.as_bytes(), &ParseOptions::strict()).unwrap();
+        let from_reader = parse_reader(std::io::BufReader::new(contents.as_bytes()), &ParseOptions::strict()).unwrap();
+        for document in [&from_str, &from_bytes, &from_reader] {
+            assert_eq!(document.get_nodes().len(), from_lines.get_nodes().len());
+            for (node, expected) in document.get_nodes().iter().zip(from_lines.get_nodes()) {
+                assert_eq!(node.get_name(), expected.get_name());
+                assert_eq!(node.get_id(), expected.get_id());
+                assert_eq!(node.get_indentation(), expected.get_indentation());
+                assert_eq!(node.get_inner_element(), expected.get_inner_element());
+            }
+        }
+
+        // A string read with fs::read_to_string keeps the byte order mark
+        let with_bom = format!("\u{FEFF}{contents}");
+        assert_eq!(parse_str(&with_bom, &ParseOptions::strict()).unwrap().get_nodes().len(), from_lines.get_nodes().len());
+
+        let utf16: Vec<u8> = [0xFF, 0xFE].into_iter().chain("<a>é</a>".encode_utf16().flat_map(u16::to_le_bytes)).collect();
+        assert_eq!(parse_reader(std::io::Cursor::new(utf16), &ParseOptions::default()).unwrap().get_nodes()[0].get_inner_element(), "é");
+    }
+
+    #[test]
+    fn test_reader_document_size_limit(){
+        let options = ParseOptions { limits: Limits { max_document_size: 7, ..Limits::default() }, ..ParseOptions::default() };
+        assert!(parse_reader("<a/>".as_bytes(), &options).is_ok());
+        let error = parse_reader("<a>\n  <b/></a>".as_bytes(), &options).unwrap_err();
+        assert_eq!(error, XmlError::LimitExceeded { limit: Limit::DocumentSize, maximum: 7, position: Position { line: 2, column: 4, offset: 7 } });
+
+        // The limit counts the text after decoding, é is one byte in Latin-1 and two in UTF-8
+        let latin1 = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><a>\xe9</a>";
+        let options = ParseOptions { limits: Limits { max_document_size: latin1.len(), ..Limits::default() }, ..ParseOptions::default() };
+        assert!(matches!(parse_bytes(latin1, &options), Err(XmlError::LimitExceeded { limit: Limit::DocumentSize, .. })));
+    }
+
     #[test]
     fn read_error_no_file() {
         let file_name = "A Random File";
-        assert_eq!(read_xml_file(file_name).unwrap_err(), "No Such File Found");
+        assert!(matches!(read_xml_file(file_name).unwrap_err(), XmlError::Io { kind: std::io::ErrorKind::NotFound, .. }));
     }
 
     #[test]
@@ -271,6 +399,7 @@ mod tests {
         let xml_prolog: String = r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string();
         let is_prolog : bool = is_prolog(&xml_prolog);
         assert!(is_prolog);
+        assert!(!super::is_prolog("<?xml-stylesheet href=\"a.xsl\"?>"));
     }
     
     #[test]
@@ -289,9 +418,15 @@ mod tests {
         let line_2 = "<section id=\"1\" name=\"Introduction\">".to_string();
         let line_3: String = "<title author=\"John Doe\" version=\"1.0\">Main Chapter</title>".to_string();
 
-        assert_eq!(get_first_tag(&line_1), "<title>");
-        assert_eq!(get_first_tag(&line_2), "<section id=\"1\" name=\"Introduction\">");
-        assert_eq!(get_first_tag(&line_3), "<title author=\"John Doe\" version=\"1.0\">");
+        assert_eq!(get_first_tag(&line_1).unwrap(), "<title>");
+        assert_eq!(get_first_tag(&line_2).unwrap(), "<section id=\"1\" name=\"Introduction\">");
+        assert_eq!(get_first_tag(&line_3).unwrap(), "<title author=\"John Doe\" version=\"1.0\">");
+    }
+
+    #[test]
+    fn test_get_first_tag_malformed(){
+        assert!(matches!(get_first_tag("no tag here"), Err(XmlError::UnexpectedEof { .. })));
+        assert!(matches!(get_first_tag("<title"), Err(XmlError::UnexpectedEof { .. })));
     }
 
     #[test]
@@ -315,32 +450,51 @@ mod tests {
     fn test_get_attributes(){
         {
             let xml_prolog: String = "<root>".to_string();
-            let first_tag :String = get_first_tag(&xml_prolog);
+            let first_tag :String = get_first_tag(&xml_prolog).unwrap();
             assert_eq!(first_tag, "<root>");
             let trimmed_tag : String = trim_line(&first_tag);
             assert_eq!(trimmed_tag, "root");
             let node_name:String = find_name(&trimmed_tag);
             assert_eq!(node_name, "root");
             let trimmed_node: String = trimmed_tag.replace(&node_name, "");
-            let attributes: HashMap<String,String> = find_attributes(&trimmed_node);
+            let attributes: Attributes = find_attributes(&trimmed_node).unwrap();
             assert_eq!(attributes.len(),0);
         }
     
         {
             let xml_prolog: String = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>".to_string();
-            let first_tag : String = get_first_tag(&xml_prolog);
+            let first_tag : String = get_first_tag(&xml_prolog).unwrap();
             assert_eq!(first_tag, xml_prolog);
             let trimmed_tag : String = trim_line(&first_tag);
             assert_eq!(trimmed_tag, "xml version=\"1.0\" encoding=\"UTF-8\"");
             let node_name:String = find_name(&trimmed_tag);
             assert_eq!(node_name, "xml");
             let trimmed_node: String = trimmed_tag.replace(&node_name, "");
-            let attributes: HashMap<String,String> = find_attributes(&trimmed_node);
+            let attributes: Attributes = find_attributes(&trimmed_node).unwrap();
             assert_eq!(attributes.get("version").unwrap(),"1.0");
             assert_eq!(attributes.get("encoding").unwrap(),"UTF-8");
         }
     }
 
+    #[test]
+    fn test_find_attributes_malformed(){
+        let unquoted = find_attributes(" version=1.0 encoding=\"UTF-8\"").unwrap_err();
+        assert!(matches!(unquoted, XmlError::InvalidAttribute { .. }));
+        let no_equals = find_attributes(" version \"1.0\"").unwrap_err();
+        assert!(matches!(no_equals, XmlError::InvalidAttribute { .. }));
+    }
+
+    #[test]
+    fn test_malformed_document_error(){
+        let example_nodes : Vec<String> = vec![
+            "<root>".to_string(),
+            "    <title author=\"John Doe>Main Chapter</title>".to_string(),
+        ];
+        let error = process_line_list(&example_nodes).unwrap_err();
+        assert!(matches!(error, XmlError::UnexpectedEof { .. }));
+        assert_eq!(error.get_position().unwrap().line, 2);
+    }
+
     #[test]
     fn test_is_comment(){
         let xml_comment: String = "<!-- This is the root node representing the document's structure -->".to_string();
@@ -351,8 +505,10 @@ mod tests {
     #[test]
     fn test_proc_mutiple_lines(){
         let line_list: Vec<String> = example_xml();
-        let list_nodes : Vec<Node> = process_line_list(&line_list);
+        let all_nodes : Vec<Node> = process_line_list(&line_list).unwrap();
+        let list_nodes = elements(&all_nodes);
 
+        assert_eq!(all_nodes.len(), 9);
         assert_eq!(list_nodes.len(), 6);
         assert_eq!(list_nodes[0].get_name(),"root");
         assert_eq!(list_nodes[1].get_name(),"head");
@@ -363,16 +519,19 @@ mod tests {
         assert_eq!(list_nodes[2].get_attribute_value("author").unwrap(),"John Doe");
         assert_eq!(list_nodes[2].get_attribute_value("version").unwrap(),"1.0");
         assert_eq!(list_nodes[2].get_inner_element(),"Main Chapter");
-        assert_eq!(list_nodes[3].get_inner_element(),"Here is a paragraph with predefined characters: &lt; &gt; &amp; &apos; &quot;");
+        // Text is kept as written, only whitespace-only text between tags is dropped by default
+        assert_eq!(list_nodes[3].get_inner_element(),"\n            Here is a paragraph with predefined characters: < > & ' \"\n        ");
+        assert_eq!(list_nodes[3].get_raw_inner_element(), None);
         assert_eq!(list_nodes[4].get_attribute_value("id").unwrap(),"chapter 1");
-        assert_eq!(list_nodes[5].get_inner_element(),"This is the story");
+        assert_eq!(list_nodes[5].get_inner_element(),"\n            This is the story\n        ");
     }
 
     #[test]
     fn test_correct_indentation(){
         let line_list: Vec<String> = example_xml();
 
-        let list_nodes : Vec<Node> = process_line_list(&line_list);
+        let all_nodes : Vec<Node> = process_line_list(&line_list).unwrap();
+        let list_nodes = elements(&all_nodes);
         assert_eq!(list_nodes[0].get_indentation(), 0);
         assert_eq!(list_nodes[1].get_indentation(), 4);
         assert_eq!(list_nodes[2].get_indentation(), 8);
@@ -385,33 +544,116 @@ mod tests {
     fn test_correct_relationship(){
         let line_list: Vec<String> = example_xml();
 
-        let list_nodes : Vec<Node> = process_line_list(&line_list);
-        assert_eq!(*list_nodes[0].get_child(), vec![1,7]);
-        assert_eq!(list_nodes[1].get_parent(), Some(0));
+        let all_nodes : Vec<Node> = process_line_list(&line_list).unwrap();
+        let list_nodes = elements(&all_nodes);
+        let id = NodeId::new;
+        assert_eq!(*list_nodes[0].get_child(), vec![id(1), id(6)]);
+        assert_eq!(list_nodes[1].get_parent(), Some(id(0)));
         assert_eq!(list_nodes[0].get_parent(), None);
 
-        assert_eq!(*list_nodes[1].get_child(), vec![2,3]);
-        assert_eq!(list_nodes[2].get_parent(), Some(1));
-        assert_eq!(list_nodes[3].get_parent(), Some(1));
+        assert_eq!(*list_nodes[1].get_child(), vec![id(2), id(4)]);
+        assert_eq!(list_nodes[2].get_parent(), Some(id(1)));
+        assert_eq!(list_nodes[3].get_parent(), Some(id(1)));
 
         
-        assert_eq!(*list_nodes[5].get_child(), vec![]);
-        assert_eq!(list_nodes[5].get_parent(), Some(7));
+        // Links are handles of the nodes in the list, text nodes included
+        assert_eq!(*list_nodes[5].get_child(), vec![id(8)]);
+        assert_eq!(list_nodes[5].get_parent(), Some(id(6)));
+        assert!(all_nodes[8].is_text());
+        assert_eq!(all_nodes[8].get_parent(), Some(id(7)));
+    }
+
+    #[test]
+    fn test_document_arena(){
+        let lines: Vec<String> = vec![
+            "<?xml version=\"1.0\"?>".to_string(),
+            "<!-- Before the root -->".to_string(),
+            "<root>".to_string(),
+            "    <a>text</a>".to_string(),
+            "</root>".to_string(),
+            "<?after?>".to_string(),
+        ];
+        let mut document = parse_document(&lines, &ParseOptions::strict()).unwrap();
+        let root = document.root().unwrap();
+        assert_eq!(root, NodeId::new(1));
+        assert_eq!(document.get(root).unwrap().get_name(), "root");
+        assert_eq!(document.get_top_level_nodes(), vec![NodeId::new(0), root, NodeId::new(4)]);
+        assert!(document.get(NodeId::new(0)).unwrap().is_comment());
+        assert!(document.get(NodeId::new(5)).is_none());
+
+        let a = document.get(root).unwrap().get_child()[0];
+        document.get_mut(a).unwrap().set_inner_element("changed".to_string());
+        assert_eq!(document.get(a).unwrap().get_inner_element(), "changed");
+        assert_eq!(document.get(a).unwrap().get_parent(), Some(root));
+        assert_eq!(document.node_ids().count(), 5);
     }
 
     #[test]
     fn test_tree_id_to_node(){
         let line_list: Vec<String> = example_xml();
 
-        let list_nodes : Vec<Node> = process_line_list(&line_list);
-        let id_to_node : HashMap<usize, &Node> = tree_id_to_node(&list_nodes);
+        let list_nodes : Vec<Node> = process_line_list(&line_list).unwrap();
+        let id_to_node : HashMap<NodeId, &Node> = tree_id_to_node(&list_nodes);
+
+        assert_eq!(id_to_node.len(), list_nodes.len());
+        assert_eq!(id_to_node.get(&NodeId::new(0)).unwrap().get_name(), "root");
+        assert_eq!(id_to_node.get(&NodeId::new(1)).unwrap().get_name(), "head");
+        assert_eq!(id_to_node.get(&NodeId::new(2)).unwrap().get_name(), "title");
+        assert_eq!(id_to_node.get(&NodeId::new(4)).unwrap().get_name(), "paragraph");
+        assert_eq!(id_to_node.get(&NodeId::new(6)).unwrap().get_name(), "body");
+        assert_eq!(id_to_node.get(&NodeId::new(7)).unwrap().get_name(), "h3");
+    }
+
+    #[test]
+    fn test_ids_do_not_depend_on_lines(){
+        let reformatted = vec![example_xml().iter().map(|line| line.trim()).collect::<String>()];
+        let nodes = process_line_list(&example_xml()).unwrap();
+        let one_line = process_line_list(&reformatted).unwrap();
+        assert_eq!(nodes.len(), one_line.len());
+        for (index, (node, other)) in nodes.iter().zip(&one_line).enumerate() {
+            assert_eq!(node.get_id(), NodeId::new(index));
+            assert_eq!(node.get_id(), other.get_id());
+            assert_eq!(node.get_child(), other.get_child());
+        }
 
-        assert_eq!(id_to_node.get(&0).unwrap().get_name(), "root");
-        assert_eq!(id_to_node.get(&1).unwrap().get_name(), "head");
-        assert_eq!(id_to_node.get(&2).unwrap().get_name(), "title");
-        assert_eq!(id_to_node.get(&3).unwrap().get_name(), "paragraph");
-        assert_eq!(id_to_node.get(&7).unwrap().get_name(), "body");
-        assert_eq!(id_to_node.get(&8).unwrap().get_name(), "h3");
+        // The source position is kept apart from the id
+        assert_eq!(nodes[6].get_location(), Some(Position { line: 8, column: 5, offset: 233 }));
+        assert_eq!(one_line[6].get_location().unwrap().line, 1);
+        assert_ne!(one_line[1].get_id(), one_line[2].get_id());
+    }
+
+    #[test]
+    fn test_node_spans(){
+        let lines = vec!["<root>".to_string(), "  <a x=\"1\">text</a><b/>".to_string(), "</root>".to_string()];
+        let document = parse_document(&lines, &ParseOptions::default()).unwrap();
+        let nodes = document.get_nodes();
+        let a = &nodes[1];
+        assert_eq!(a.get_start_tag_span().unwrap().to_string(), "2:3-2:12");
+        assert_eq!(a.get_content_span().unwrap(), Span { start_line: 2, start_col: 12, end_line: 2, end_col: 16, byte_range: 18..22 });
+        assert_eq!(a.get_end_tag_span().unwrap().to_string(), "2:16-2:20");
+        assert_eq!(a.get_span().unwrap().to_string(), "2:3-2:20");
+        assert_eq!(&lines.join("\n")[a.get_span().unwrap().byte_range.clone()], "<a x=\"1\">text</a>");
+        assert_eq!(nodes[2].get_span().unwrap().to_string(), "2:12-2:16");
+
+        let b = &nodes[3];
+        assert_eq!(b.get_span().unwrap().to_string(), "2:20-2:24");
+        assert_eq!(b.get_end_tag_span(), None);
+        assert_eq!(b.get_content_span(), None);
+        assert_eq!(nodes[0].get_span().unwrap().to_string(), "1:1-3:8");
+
+        // Elements closed by recovery have no end tag of their own
+        let recovered = parse_document(&["<a><b>x</a>".to_string()], &ParseOptions::default()).unwrap();
+        let (a, b) = (recovered.get_nodes()[0], recovered.get_nodes()[1]);
+        assert_eq!(a.get_end_tag_span().unwrap().to_string(), "1:8-1:12");
+        assert_eq!(a.get_content_span().unwrap().to_string(), "1:4-1:8");
+        assert_eq!(b.get_end_tag_span(), None);
+        assert_eq!(b.get_content_span(), None);
+        assert_eq!(b.get_span().unwrap().to_string(), "1:4-1:8");
+
+        // An element closed by the end of the input has no end tag
+        let unclosed = parse_document(&["<a>text".to_string()], &ParseOptions::default()).unwrap();
+        assert_eq!(unclosed.get_nodes()[0].get_span().unwrap().to_string(), "1:1-1:8");
+        assert_eq!(unclosed.get_nodes()[0].get_end_tag_span(), None);
     }
 
     #[test]
@@ -422,9 +664,508 @@ mod tests {
             "</root>".to_string()
         ];
 
-        let result = process_line_list(&example_nodes);
+        let document = parse_document(&example_nodes, &ParseOptions::default()).unwrap();
+        let result = document.get_nodes();
+        assert_eq!(result.len(), 6);
         let node_for = &result[1];
-        assert_eq!(node_for.get_id(), 1);
-        assert_eq!(node_for.get_inner_element(), "“There <em>are</em> some angels without wings, little Grissel. Not many I admit; but I have known a few.”");
+        assert_eq!(node_for.get_id(), NodeId::new(1));
+        assert_eq!(node_for.get_name(), "p");
+        assert!(result[2].is_text());
+        assert_eq!(result[2].get_inner_element(), "“There ");
+        assert_eq!(result[3].get_name(), "em");
+        assert_eq!(result[3].get_inner_element(), "are");
+        assert_eq!(result[3].get_parent(), Some(NodeId::new(1)));
+        assert!(result[4].is_text());
+        assert_eq!(result[4].get_depth(), 3);
+        assert_eq!(result[5].get_inner_element(), " some angels without wings, little Grissel. Not many I admit; but I have known a few.”");
+        assert_eq!(result[5].get_depth(), 2);
+
+        assert_eq!(node_for.get_child().len(), 3);
+        assert_eq!(document.text_content(NodeId::new(1)), "“There are some angels without wings, little Grissel. Not many I admit; but I have known a few.”");
+        assert_eq!(document.text_content(NodeId::new(0)), document.text_content(NodeId::new(1)));
+        assert_eq!(document.text_content(NodeId::new(3)), "are");
+    }
+
+    #[test]
+    fn test_mixed_text_and_cdata(){
+        let lines = vec!["<a>x &amp; <![CDATA[ <y> ]]> z<b>!</b></a>".to_string()];
+        let document = parse_document(&lines, &ParseOptions::default()).unwrap();
+        let nodes = document.get_nodes();
+        assert_eq!(nodes.len(), 6);
+        assert_eq!(nodes[1].get_inner_element(), "x & ");
+        assert!(nodes[2].is_cdata());
+        assert_eq!(nodes[0].get_inner_element(), "x &  <y>  z");
+        assert!(nodes[0].is_cdata());
+        assert_eq!(document.text_content(NodeId::new(0)), "x &  <y>  z!");
+        assert_eq!(document.text_content(NodeId::new(4)), "!");
+        assert_eq!(document.text_content(NodeId::new(5)), "!");
+    }
+
+    fn parse_with_whitespace(xml: &str, whitespace: Whitespace) -> Document {
+        let lines: Vec<String> = xml.lines().map(|line| line.to_string()).collect();
+        parse_document(&lines, &ParseOptions { whitespace, ..ParseOptions::default() }).unwrap()
+    }
+
+    #[test]
+    fn test_whitespace_modes(){
+        let xml = "<config>\n  <name>  Main   Server </name>\n  <p><b>a</b> <i>b</i></p>\n</config>";
+        let texts = |document: &Document| -> Vec<String> {
+            document.get_nodes().iter().filter(|node| node.is_text()).map(|node| node.get_inner_element().clone()).collect()
+        };
+
+        let document = parse_with_whitespace(xml, Whitespace::Preserve);
+        assert_eq!(texts(&document), vec!["\n  ", "  Main   Server ", "\n  ", "a", " ", "b", "\n"]);
+        assert_eq!(document.get_nodes()[2].get_inner_element(), "  Main   Server ");
+        assert_eq!(document.text_content(NodeId::new(5)), "a b");
+
+        let document = parse_with_whitespace(xml, Whitespace::IgnoreWhitespaceOnly);
+        assert_eq!(texts(&document), vec!["  Main   Server ", "a", "b"]);
+        assert_eq!(document.get_nodes()[1].get_inner_element(), "  Main   Server ");
+
+        let document = parse_with_whitespace(xml, Whitespace::Trim);
+        assert_eq!(texts(&document), vec!["Main   Server", "a", "b"]);
+
+        let document = parse_with_whitespace(xml, Whitespace::Collapse);
+        assert_eq!(texts(&document), vec!["Main Server", "a", "b"]);
+    }
+
+    #[test]
+    fn test_xml_space_preserve(){
+        let xml = "<doc>\n  <pre xml:space=\"preserve\">  two  spaces\n    <code> x </code>\n  </pre>\n  <p xml:space=\"preserve\"><q xml:space=\"default\"> y </q></p>\n</doc>";
+        let document = parse_with_whitespace(xml, Whitespace::Collapse);
+        let nodes = document.get_nodes();
+        assert!(nodes[1].is_space_preserved());
+        assert_eq!(nodes[1].get_inner_element(), "  two  spaces\n    \n  ");
+        assert!(nodes[3].is_space_preserved());
+        assert_eq!(nodes[3].get_inner_element(), " x ");
+        let q = nodes.iter().find(|node| node.get_name() == "q").unwrap();
+        assert!(!q.is_space_preserved());
+        assert_eq!(q.get_inner_element(), "y");
+
+        let lines = vec!["<a xml:space=\"keep\"/>".to_string()];
+        assert!(matches!(parse_document(&lines, &ParseOptions::strict()), Err(XmlError::InvalidAttribute { .. })));
+        assert!(parse_document(&lines, &ParseOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_single_line_document(){
+        let example_nodes : Vec<String> = vec!["<a><b/><c x=\"1\">Text</c></a>".to_string()];
+
+        let all_nodes = process_line_list(&example_nodes).unwrap();
+        let result = elements(&all_nodes);
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[0].get_name(), "a");
+        assert!(result[0].is_root());
+        assert_eq!(result[1].get_name(), "b");
+        assert_eq!(result[2].get_name(), "c");
+        assert_eq!(result[2].get_attribute_value("x").unwrap(), "1");
+        assert_eq!(result[2].get_inner_element(), "Text");
+        assert!(!result[2].is_root());
+    }
+
+    #[test]
+    fn test_start_tag_over_multiple_lines(){
+        let example_nodes : Vec<String> = vec![
+            "<root>".to_string(),
+            "    <section id=\"1\"".to_string(),
+            "             name=\"Introduction\">Hello</section>".to_string(),
+            "</root>".to_string()
+        ];
+
+        let all_nodes = process_line_list(&example_nodes).unwrap();
+        let result = elements(&all_nodes);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[1].get_name(), "section");
+        assert_eq!(result[1].get_attribute_value("name").unwrap(), "Introduction");
+        assert_eq!(result[1].get_inner_element(), "Hello");
+        assert_eq!(result[1].get_indentation(), 4);
+        assert_eq!(*result[0].get_child(), vec![NodeId::new(1)]);
+    }
+
+    fn strict_parse(xml: &str) -> Result<Vec<Node>, XmlError> {
+        let lines: Vec<String> = xml.lines().map(|line| line.to_string()).collect();
+        process_line_list_with_options(&lines, &ParseOptions::strict())
+    }
+
+    #[test]
+    fn test_strict_accepts_well_formed(){
+        let list_nodes = process_line_list_with_options(&example_xml(), &ParseOptions::strict()).unwrap();
+        assert_eq!(elements(&list_nodes).len(), 6);
+    }
+
+    #[test]
+    fn test_strict_mismatched_end_tag(){
+        let error = strict_parse("<a>\n  <b></a>").unwrap_err();
+        assert_eq!(error, XmlError::MismatchedEndTag {
+            expected: "b".to_string(),
+            found: "a".to_string(),
+            position: Position { line: 2, column: 6, offset: 9 },
+        });
+        // The lenient parser recovers by closing both elements
+        let lines = vec!["<a>".to_string(), "  <b></a>".to_string()];
+        assert_eq!(process_line_list(&lines).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_strict_single_root(){
+        assert!(matches!(strict_parse("<a/><b/>"), Err(XmlError::InvalidSyntax { .. })));
+        assert!(matches!(strict_parse("<a/>text"), Err(XmlError::InvalidSyntax { .. })));
+        assert!(matches!(strict_parse("<!-- only a comment -->"), Err(XmlError::UnexpectedEof { .. })));
+        assert!(strict_parse("<a/>\n  ").is_ok());
+    }
+
+    #[test]
+    fn test_strict_unclosed_element(){
+        let error = strict_parse("<a>\n<b>").unwrap_err();
+        assert_eq!(error, XmlError::UnexpectedEof {
+            expected: "</b>".to_string(),
+            position: Position { line: 2, column: 4, offset: 7 },
+        });
+    }
+
+    #[test]
+    fn test_strict_attributes(){
+        assert!(matches!(strict_parse("<a x=\"1\" x=\"2\"/>"), Err(XmlError::InvalidAttribute { .. })));
+        assert!(matches!(strict_parse("<a x=\"1<2\"/>"), Err(XmlError::InvalidCharacter { character: '<', .. })));
+        assert!(matches!(strict_parse("<a 1x=\"1\"/>"), Err(XmlError::InvalidAttribute { .. })));
+    }
+
+    #[test]
+    fn test_strict_name_characters(){
+        let error = strict_parse("<root>\n  <1st/>\n</root>").unwrap_err();
+        assert_eq!(error, XmlError::InvalidCharacter {
+            character: '1',
+            position: Position { line: 2, column: 4, offset: 10 },
+        });
+        assert!(strict_parse("<r xmlns:ns=\"urn:ns\"><ns:élément-1.a/></r>").is_ok());
+    }
+
+    #[test]
+    fn test_entities_in_attributes_and_text(){
+        let lines = vec!["<a title=\"Fish &amp; Chips &#169;\">&#x1F600; &lt;b&gt;</a>".to_string()];
+        let list_nodes = process_line_list(&lines).unwrap();
+        assert_eq!(list_nodes[0].get_attribute_value("title").unwrap(), "Fish & Chips ©");
+        assert_eq!(list_nodes[0].get_inner_element(), "😀 <b>");
+        assert_eq!(list_nodes[0].get_raw_attribute_value("title"), None);
+    }
+
+    #[test]
+    fn test_attributes_in_document_order(){
+        let lines = vec!["<config zeta=\"1\" alpha=\"2\" mid=\"3\" beta=\"4\"/>".to_string()];
+        let list_nodes = process_line_list(&lines).unwrap();
+        let names: Vec<&String> = list_nodes[0].get_all_attributes().iter().map(|attribute| attribute.get_name()).collect();
+        assert_eq!(names, vec!["zeta", "alpha", "mid", "beta"]);
+        assert_eq!(list_nodes[0].get_all_attributes().get("mid").unwrap(), "3");
+    }
+
+    #[test]
+    fn test_namespaces(){
+        let xml = "<soap:Envelope xmlns:soap=\"http://www.w3.org/2003/05/soap-envelope\" xmlns=\"urn:default\">\n  <soap:Body>\n    <order xmlns:o=\"urn:orders\" o:id=\"7\" status=\"new\" xml:lang=\"en\"/>\n  </soap:Body>\n</soap:Envelope>";
+        let lines: Vec<String> = xml.lines().map(String::from).collect();
+        let document = parse_document(&lines, &ParseOptions::strict()).unwrap();
+        let nodes = document.get_nodes();
+
+        assert_eq!(nodes[0].get_prefix(), Some("soap"));
+        assert_eq!(nodes[0].get_local_name(), "Envelope");
+        assert_eq!(nodes[0].get_namespace_uri().unwrap(), "http://www.w3.org/2003/05/soap-envelope");
+        assert_eq!(nodes[1].get_namespaces().lookup(None), Some("urn:default"));
+
+        let order = &nodes[2];
+        assert_eq!(order.get_namespace_uri().unwrap(), "urn:default");
+        assert_eq!(order.get_namespaces().get_bindings().len(), 3);
+        let attributes = order.get_all_attributes();
+        assert_eq!(attributes.get_ns(Some("urn:orders"), "id").unwrap().get_value(), "7");
+        assert_eq!(attributes.get_ns(None, "status").unwrap().get_value(), "new");
+        assert_eq!(attributes.get_ns(Some(crate::namespaces::XML_NAMESPACE), "lang").unwrap().get_value(), "en");
+
+        let bodies = document.get_elements_ns(Some("http://www.w3.org/2003/05/soap-envelope"), "Body");
+        assert_eq!(bodies.len(), 1);
+        assert!(document.get_elements_ns(None, "order").is_empty());
+    }
+
+    #[test]
+    fn test_strict_undeclared_prefix(){
+        let error = strict_parse("<a>\n  <x:b/>\n</a>").unwrap_err();
+        assert_eq!(error, XmlError::UndeclaredPrefix { prefix: "x".to_string(), position: Position { line: 2, column: 3, offset: 6 } });
+        assert!(strict_parse("<a x:id=\"1\"/>").is_err());
+
+        let lines = vec!["<x:a/>".to_string()];
+        let list_nodes = process_line_list(&lines).unwrap();
+        assert_eq!(list_nodes[0].get_namespace_uri(), None);
+        assert_eq!(list_nodes[0].get_local_name(), "a");
+    }
+
+    #[test]
+    fn test_retain_raw_values(){
+        let options = ParseOptions { retain_raw: true, ..ParseOptions::default() };
+        let all_nodes = process_line_list_with_options(&example_xml(), &options).unwrap();
+        let list_nodes = elements(&all_nodes);
+        assert_eq!(list_nodes[3].get_raw_inner_element().unwrap(), "\n            Here is a paragraph with predefined characters: &lt; &gt; &amp; &apos; &quot;\n        ");
+        assert_eq!(list_nodes[2].get_raw_attribute_value("author").unwrap(), "John Doe");
+    }
+
+    #[test]
+    fn test_strict_undefined_entity(){
+        let error = strict_parse("<a>\n    Fish &chips;</a>").unwrap_err();
+        assert_eq!(error, XmlError::UndefinedEntity {
+            name: "chips".to_string(),
+            position: Position { line: 2, column: 10, offset: 13 },
+        });
+        let lines = vec!["<a>Fish &chips;</a>".to_string()];
+        assert_eq!(process_line_list(&lines).unwrap()[0].get_inner_element(), "Fish &chips;");
+    }
+
+    #[test]
+    fn test_cdata_section(){
+        let lines: Vec<String> = vec![
+            "<config>".to_string(),
+            "    <script><![CDATA[".to_string(),
+            "if (a < b && b > c) { run(\"&amp;\"); }".to_string(),
+            "]]></script>".to_string(),
+            "    <query> <![CDATA[SELECT * FROM t WHERE x <> 1]]> </query>".to_string(),
+            "    <name>plain &amp; simple</name>".to_string(),
+            "</config>".to_string(),
+        ];
+        let all_nodes = process_line_list_with_options(&lines, &ParseOptions::strict()).unwrap();
+        let list_nodes = elements(&all_nodes);
+        assert_eq!(list_nodes[1].get_inner_element(), "\nif (a < b && b > c) { run(\"&amp;\"); }\n");
+        assert!(list_nodes[1].is_cdata());
+        assert_eq!(list_nodes[2].get_inner_element(), "SELECT * FROM t WHERE x <> 1");
+        assert!(list_nodes[2].is_cdata());
+        assert_eq!(list_nodes[3].get_inner_element(), "plain & simple");
+        assert!(!list_nodes[3].is_cdata());
+    }
+
+    #[test]
+    fn test_unterminated_cdata(){
+        let lines = vec!["<a><![CDATA[ never closed </a>".to_string()];
+        assert!(matches!(process_line_list(&lines), Err(XmlError::UnexpectedEof { .. })));
+    }
+
+    #[test]
+    fn test_xml_declaration(){
+        let lines = vec![
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>".to_string(),
+            "<root/>".to_string(),
+        ];
+        let document = parse_document(&lines, &ParseOptions::strict()).unwrap();
+        let declaration = document.get_declaration().unwrap();
+        assert_eq!(declaration.get_version(), "1.0");
+        assert_eq!(declaration.get_encoding().unwrap(), "UTF-8");
+        assert_eq!(declaration.get_standalone(), Some(false));
+        assert_eq!(document.get_nodes().len(), 1);
+
+        let document = parse_document(&example_xml(), &ParseOptions::default()).unwrap();
+        assert!(document.get_declaration().is_none());
+    }
+
+    #[test]
+    fn test_invalid_xml_declaration(){
+        let lines = vec!["<?xml version=\"1.0\" standalone=\"maybe\"?><root/>".to_string()];
+        let error = parse_document(&lines, &ParseOptions::default()).unwrap_err();
+        assert_eq!(error.get_position().unwrap().column, 33);
+    }
+
+    #[test]
+    fn test_misplaced_xml_declaration(){
+        let lines = vec!["<root/>".to_string(), "<?xml version=\"1.0\"?>".to_string()];
+        assert!(parse_document(&lines, &ParseOptions::default()).unwrap().get_declaration().is_none());
+        assert!(matches!(parse_document(&lines, &ParseOptions::strict()), Err(XmlError::InvalidSyntax { .. })));
+        let lines = vec!["  <?xml version=\"1.0\"?><root/>".to_string()];
+        assert!(parse_document(&lines, &ParseOptions::strict()).is_err());
+    }
+
+    #[test]
+    fn test_comment_nodes(){
+        let lines: Vec<String> = vec![
+            "<!-- Before the root -->".to_string(),
+            "<root>".to_string(),
+            "    <a/>".to_string(),
+            "    <!-- Spanning".to_string(),
+            "         two lines -->".to_string(),
+            "    <b/>".to_string(),
+            "</root>".to_string(),
+        ];
+        let list_nodes = process_line_list_with_options(&lines, &ParseOptions::strict()).unwrap();
+        assert_eq!(list_nodes.len(), 5);
+        assert!(list_nodes[0].is_comment());
+        assert_eq!(list_nodes[0].get_inner_element(), " Before the root ");
+        assert_eq!(list_nodes[0].get_parent(), None);
+        assert!(list_nodes[1].is_root());
+        assert!(list_nodes[3].is_comment());
+        assert_eq!(list_nodes[3].get_inner_element(), " Spanning\n         two lines ");
+        assert_eq!(list_nodes[3].get_parent(), Some(NodeId::new(1)));
+        assert_eq!(*list_nodes[1].get_child(), vec![NodeId::new(2), NodeId::new(3), NodeId::new(4)]);
+    }
+
+    #[test]
+    fn test_strict_comment_content(){
+        assert!(matches!(strict_parse("<a><!-- a -- b --></a>"), Err(XmlError::InvalidSyntax { .. })));
+        assert!(strict_parse("<a><!-- a - b --></a>").is_ok());
+    }
+
+    #[test]
+    fn test_trim_line_keeps_question_marks(){
+        assert_eq!(trim_line("<question>Why?</question>"), "questionWhy?question");
+        assert_eq!(find_name("<question>"), "question");
+    }
+
+    #[test]
+    fn test_processing_instruction_nodes(){
+        let lines: Vec<String> = vec![
+            "<?xml version=\"1.0\"?>".to_string(),
+            "<?xml-stylesheet type=\"text/xsl\" href=\"a.xsl\"?>".to_string(),
+            "<root>".to_string(),
+            "    <?php echo \"Why?\"; ?>".to_string(),
+            "    <q>Why?</q>".to_string(),
+            "</root>".to_string(),
+        ];
+        let document = parse_document(&lines, &ParseOptions::strict()).unwrap();
+        let list_nodes = document.get_nodes();
+        assert_eq!(document.get_declaration().unwrap().get_version(), "1.0");
+        assert_eq!(list_nodes.len(), 5);
+        assert_eq!(list_nodes[0].target(), Some("xml-stylesheet"));
+        assert_eq!(list_nodes[0].data(), Some("type=\"text/xsl\" href=\"a.xsl\""));
+        assert_eq!(list_nodes[0].get_parent(), None);
+        assert_eq!(list_nodes[2].target(), Some("php"));
+        assert_eq!(list_nodes[2].data(), Some("echo \"Why?\"; "));
+        assert_eq!(list_nodes[2].get_parent(), Some(NodeId::new(1)));
+        assert_eq!(list_nodes[3].get_inner_element(), "Why?");
+        assert_eq!(document.get_processing_instructions().len(), 2);
+    }
+
+    #[test]
+    fn test_strict_reserved_target(){
+        assert!(matches!(strict_parse("<a><?XML data?></a>"), Err(XmlError::InvalidSyntax { .. })));
+    }
+
+    #[test]
+    fn test_doctype_entities(){
+        let lines: Vec<String> = vec![
+            "<?xml version=\"1.0\"?>".to_string(),
+            "<!DOCTYPE letter SYSTEM \"letter.dtd\" [".to_string(),
+            "    <!ENTITY company \"Acme &amp; Sons\">".to_string(),
+            "    <!ENTITY signature \"Regards, &company;\">".to_string(),
+            "]>".to_string(),
+            "<letter from=\"&company;\">".to_string(),
+            "    <body>&signature;</body>".to_string(),
+            "</letter>".to_string(),
+        ];
+        let document = parse_document(&lines, &ParseOptions::strict()).unwrap();
+        let doctype = document.get_doctype().unwrap();
+        assert_eq!(doctype.get_root_name(), "letter");
+        assert_eq!(doctype.get_system_id().unwrap(), "letter.dtd");
+        assert_eq!(doctype.get_entity("company").unwrap(), "Acme &amp; Sons");
+
+        let nodes = document.into_nodes();
+        let list_nodes = elements(&nodes);
+        assert_eq!(list_nodes.len(), 2);
+        assert_eq!(list_nodes[0].get_name(), "letter");
+        assert_eq!(list_nodes[0].get_attribute_value("from").unwrap(), "Acme & Sons");
+        assert_eq!(list_nodes[1].get_inner_element(), "Regards, Acme & Sons");
+    }
+
+    #[test]
+    fn test_strict_markup_in_entities(){
+        let lines = vec!["<!DOCTYPE a [<!ENTITY lt2 \"<\">]>".to_string(), "<a x=\"&lt2;\"/>".to_string()];
+        assert!(matches!(parse_document(&lines, &ParseOptions::strict()), Err(XmlError::InvalidSyntax { .. })));
+        let document = parse_document(&lines, &ParseOptions::default()).unwrap();
+        assert_eq!(document.get_nodes()[0].get_attribute_value("x").unwrap(), "<");
+    }
+
+    #[test]
+    fn test_misplaced_doctype(){
+        let lines = vec!["<a/>".to_string(), "<!DOCTYPE a>".to_string()];
+        assert!(parse_document(&lines, &ParseOptions::default()).unwrap().get_doctype().is_none());
+        assert!(matches!(parse_document(&lines, &ParseOptions::strict()), Err(XmlError::InvalidSyntax { .. })));
+    }
+
+    fn parse_with_limits(xml: &str, limits: Limits) -> Result<Document, XmlError> {
+        let lines: Vec<String> = xml.lines().map(|line| line.to_string()).collect();
+        parse_document(&lines, &ParseOptions { limits, ..ParseOptions::default() })
+    }
+
+    #[test]
+    fn test_element_depth_limit(){
+        let limits = Limits { max_element_depth: 3, ..Limits::default() };
+        assert!(parse_with_limits("<a><b><c/></b></a>", limits).is_ok());
+        let error = parse_with_limits("<a><b><c><d/></c></b></a>", limits).unwrap_err();
+        assert_eq!(error, XmlError::LimitExceeded {
+            limit: Limit::ElementDepth,
+            maximum: 3,
+            position: Position { line: 1, column: 10, offset: 9 },
+        });
+        let deep = "<a>".repeat(100_000);
+        assert!(matches!(parse_with_limits(&deep, Limits::default()), Err(XmlError::LimitExceeded { limit: Limit::ElementDepth, .. })));
+    }
+
+    #[test]
+    fn test_attribute_and_name_limits(){
+        let limits = Limits { max_attributes: 2, max_name_length: 5, ..Limits::default() };
+        assert!(parse_with_limits("<a x=\"1\" y=\"2\"/>", limits).is_ok());
+        assert!(matches!(parse_with_limits("<a x=\"1\" y=\"2\" z=\"3\"/>", limits), Err(XmlError::LimitExceeded { limit: Limit::Attributes, .. })));
+        assert!(matches!(parse_with_limits("<abcdef/>", limits), Err(XmlError::LimitExceeded { limit: Limit::NameLength, .. })));
+        assert!(matches!(parse_with_limits("<a abcdef=\"1\"/>", limits), Err(XmlError::LimitExceeded { limit: Limit::NameLength, .. })));
+        assert!(matches!(parse_with_limits("<a><?abcdef?></a>", limits), Err(XmlError::LimitExceeded { limit: Limit::NameLength, .. })));
+
+        // A tag with a huge number of attributes stops at the limit, and without one duplicates are found quickly
+        let attributes: String = (0..40_000).map(|index| format!(" a{index}=\"1\"")).collect();
+        let error = parse_with_limits(&format!("<a{attributes}/>"), Limits::default()).unwrap_err();
+        assert_eq!(error, XmlError::LimitExceeded { limit: Limit::Attributes, maximum: 256, position: Position { line: 1, column: 2198, offset: 2197 } });
+        let error = parse_with_limits(&format!("<a{attributes} a0=\"2\"/>"), Limits::unlimited()).unwrap_err();
+        assert!(matches!(error, XmlError::InvalidAttribute { .. }));
+    }
+
+    #[test]
+    fn test_document_size_limit(){
+        assert!(Limits::default().max_document_size < Limits::unlimited().max_document_size);
+        let limits = Limits { max_document_size: 7, ..Limits::default() };
+        assert!(parse_with_limits("<a/>", limits).is_ok());
+        let error = parse_with_limits("<a>\n  é</a>", limits).unwrap_err();
+        assert_eq!(error.get_position(), Some(Position { line: 2, column: 3, offset: 6 }));
+    }
+
+    #[test]
+    fn test_billion_laughs_document(){
+        let mut lines = vec!["<!DOCTYPE lolz [".to_string(), "<!ENTITY lol \"lol\">".to_string()];
+        lines.push("<!ENTITY lol1 \"&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;&lol;\">".to_string());
+        for level in 2..10 {
+            lines.push(format!("<!ENTITY lol{level} \"{}\">", format!("&lol{};", level - 1).repeat(10)));
+        }
+        lines.push("]>".to_string());
+        lines.push("<lolz>&lol9;</lolz>".to_string());
+        let error = parse_document(&lines, &ParseOptions::default()).unwrap_err();
+        assert!(matches!(error, XmlError::LimitExceeded { limit: Limit::EntityExpansionSize, .. }));
+        assert_eq!(error.get_position().unwrap().line, 13);
+    }
+
+    #[test]
+    fn test_find_attributes_grammar(){
+        let attributes = find_attributes("a=\"b\"").unwrap();
+        assert_eq!(attributes.get("a").unwrap(), "b");
+        let attributes = find_attributes(" key = 'value'\tother=\"it's\"\n").unwrap();
+        assert_eq!(attributes.get("key").unwrap(), "value");
+        assert_eq!(attributes.get("other").unwrap(), "it's");
+        assert!(matches!(find_attributes("a=\"1\" a=\"2\""), Err(XmlError::InvalidAttribute { .. })));
+    }
+
+    #[test]
+    fn test_attribute_parsing_in_documents(){
+        let lines: Vec<String> = vec![
+            "<root>".to_string(),
+            "    <item name = 'single'".to_string(),
+            "\tnote=\"two\tlines".to_string(),
+            "here\" code=\"a&#10;b\"/>".to_string(),
+            "</root>".to_string(),
+        ];
+        let list_nodes = process_line_list(&lines).unwrap();
+        assert_eq!(list_nodes[1].get_attribute_value("name").unwrap(), "single");
+        assert_eq!(list_nodes[1].get_attribute_value("note").unwrap(), "two lines here");
+        assert_eq!(list_nodes[1].get_attribute_value("code").unwrap(), "a\nb");
+
+        let duplicate = vec!["<a x=\"1\" x=\"2\"/>".to_string()];
+        assert!(matches!(process_line_list(&duplicate), Err(XmlError::InvalidAttribute { .. })));
+        let unquoted = vec!["<a x=1/>".to_string()];
+        assert!(matches!(process_line_list(&unquoted), Err(XmlError::InvalidAttribute { .. })));
     }
 }